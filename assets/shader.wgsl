@@ -14,11 +14,15 @@ struct ModelVertex {
 @group(#{MATERIAL_BIND_GROUP}) @binding(1) var my_array_texture_sampler: sampler;
 @group(#{MATERIAL_BIND_GROUP}) @binding(2) var<uniform> ao_factor: f32;
 @group(#{MATERIAL_BIND_GROUP}) @binding(3) var<storage, read> model_buffer: array<f32>;
+@group(#{MATERIAL_BIND_GROUP}) @binding(4) var<uniform> alpha_cutoff: f32;
 
 struct VertexInput {
     @builtin(instance_index) instance_index: u32,
     @location(0) data: u32,
     @location(1) texture_index: u32,
+    @location(2) size: u32,
+    @location(3) tint: u32,
+    @location(4) light: u32,
 }
 
 struct VertexOutput {
@@ -26,6 +30,8 @@ struct VertexOutput {
     @location(0) tex_coords: vec2<f32>,
     @location(1) ao: f32,
     @location(2) @interpolate(flat) texture_index: u32,
+    @location(3) @interpolate(flat) tint: vec3<f32>,
+    @location(4) light: f32,
 }
 
 @vertex
@@ -38,7 +44,6 @@ fn vs_main(
     let pos_z = (input.data >> 15) & 0x1F;  // 5 bits
     let vertex_idx = (input.data >> 3) & 0xFFF;  // 12 bits
     let ao = (input.data >> 1) & 0x03;  // 2 bits
-    let is_transparent = input.data & 0x01;  // 1 bit
 
     // Calculate offset into model buffer
     // Each model starts at a different offset, but for now we can calculate directly
@@ -61,29 +66,56 @@ fn vs_main(
         model_buffer[buffer_idx + 7u]
     );
 
+    // Merged quads stretch a cube face over several blocks, repeating the texture across them
+    let size = vec3<f32>(
+        f32(((input.size >> 10) & 0x1F) + 1u),
+        f32(((input.size >> 5) & 0x1F) + 1u),
+        f32((input.size & 0x1F) + 1u)
+    );
+    var uv_scale: vec2<f32>;
+    if (abs(model_normal.x) > 0.5) {
+        uv_scale = size.zy;
+    } else if (abs(model_normal.y) > 0.5) {
+        uv_scale = size.xz;
+    } else {
+        uv_scale = size.xy;
+    }
+
     // Combine chunk position with model position
     let block_pos = vec3<f32>(f32(pos_x), f32(pos_y), f32(pos_z));
-    let final_position = vec4<f32>(block_pos + model_position, 1.0);
+    let final_position = vec4<f32>(block_pos + model_position * size, 1.0);
 
     var out: VertexOutput;
-    out.tex_coords = model_uv;
+    out.tex_coords = model_uv * uv_scale;
     let ao_value = f32(ao) / 3.0;
     out.ao = mix(1.0, ao_value, ao_factor);
     out.clip_position = mesh_position_local_to_clip(get_world_from_local(input.instance_index), final_position);
-    
-    // Apply depth offset for transparent faces to prevent z-fighting
-    if (is_transparent != 0u) {
-        out.clip_position.z += 0.0001;
-    }
-    
     out.texture_index = input.texture_index;
+
+    // Tints are sRGB, but the texture is sampled as linear
+    let srgb_tint = vec3<f32>(
+        f32((input.tint >> 16) & 0xFFu),
+        f32((input.tint >> 8) & 0xFFu),
+        f32(input.tint & 0xFFu)
+    ) / 255.0;
+    out.tint = pow(srgb_tint, vec3<f32>(2.2));
+
+    // Each light level is a fixed fraction darker than the one above, interpolated across the
+    // face so light fades smoothly
+    let sky_light = f32((input.light >> 4) & 0xFu);
+    let block_light = f32(input.light & 0xFu);
+    out.light = pow(0.8, 15.0 - max(sky_light, block_light));
     return out;
 }
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let texture_color = textureSampleLevel(my_array_texture, my_array_texture_sampler, in.tex_coords, in.texture_index, 0.0);
-    let color = vec4<f32>(texture_color.rgb * in.ao, texture_color.a);
+    let texture_color = textureSampleLevel(my_array_texture, my_array_texture_sampler, fract(in.tex_coords), in.texture_index, 0.0);
+    let color = vec4<f32>(texture_color.rgb * in.tint * in.ao * in.light, texture_color.a);
+
+    if (color.a < alpha_cutoff) {
+        discard;
+    }
 
     #ifdef OIT_ENABLED
         oit_draw(in.clip_position, color);
@@ -0,0 +1,55 @@
+//! Compares region file sizes for each codec on generated terrain.
+//!
+//! Run with `cargo run --release --example region_compression --features zstd`.
+
+use std::{collections::HashMap, fs, path::Path, time::Instant};
+
+use bevy::math::IVec3;
+use voxel::{Codec, RegionManager, Registry, WorldGenerator};
+
+fn main() {
+    let mut registry = Registry::new();
+    registry.register_defaults();
+
+    let generator = WorldGenerator::new();
+
+    // A slab of terrain around the surface, spanning a few regions
+    let mut chunks = HashMap::new();
+
+    for x in -8..8 {
+        for y in -1..3 {
+            for z in -8..8 {
+                let chunk_pos = IVec3::new(x, y, z);
+                chunks.insert(chunk_pos, generator.generate_chunk(chunk_pos, &registry));
+            }
+        }
+    }
+
+    println!("Saving {} chunks", chunks.len());
+
+    for &codec in Codec::ALL {
+        let dir = std::env::temp_dir().join(format!("voxel-region-compression-{codec}"));
+        let _ = fs::remove_dir_all(&dir);
+
+        let region_manager = RegionManager::with_codec(dir.clone(), codec).unwrap();
+
+        let start = Instant::now();
+        region_manager.save_chunks(&chunks).unwrap();
+        let elapsed = start.elapsed();
+
+        println!(
+            "{codec:>5}: {:>8.1} KiB in {:>6.1} ms",
+            directory_size(&dir) as f64 / 1024.0,
+            elapsed.as_secs_f64() * 1000.0
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+fn directory_size(path: &Path) -> u64 {
+    fs::read_dir(path)
+        .unwrap()
+        .map(|entry| entry.unwrap().metadata().unwrap().len())
+        .sum()
+}
@@ -2,7 +2,8 @@ use bevy::{math::USizeVec3, prelude::*};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    Aabb, BlockId, ChunkMeshBuilder, ChunkVertex, ModelId, PackedData, Registry, RelevantChunks,
+    Aabb, BlockId, ChunkMeshBuilder, ChunkVertex, Light, MaterialId, ModelId, PackedData, Registry,
+    RelevantChunks,
 };
 
 pub trait BlockType: 'static + Send + Sync {
@@ -33,6 +34,33 @@ pub trait BlockType: 'static + Send + Sync {
         self.face_rect(face, data)
             .is_some_and(|face_rect| !face_rect.is_transparent)
     }
+
+    /// Whether every face covers the whole side of the block and isn't transparent, so the block
+    /// hides any face that touches it.
+    fn is_opaque_cube(&self, data: PackedData) -> bool {
+        BlockFace::ALL.into_iter().all(|face| {
+            self.face_rect(face, data).is_some_and(|face_rect| {
+                !face_rect.is_transparent && face_rect.rect == Rect::new(0.0, 0.0, 1.0, 1.0)
+            })
+        })
+    }
+
+    /// How much block light the block gives off, from 0 for none up to `Light::MAX`.
+    fn light_emission(&self, _data: PackedData) -> u8 {
+        0
+    }
+
+    /// Rewrites the materials stored in the block data, for worlds that were saved with different
+    /// material ids. Returns `None` if any of the materials can't be mapped.
+    ///
+    /// There's no default, since a block that stores materials and forgets to map them would save
+    /// registry ids that change with the registration order. Blocks without materials return the
+    /// data as is.
+    fn map_materials(
+        &self,
+        data: PackedData,
+        map: &dyn Fn(MaterialId) -> Option<MaterialId>,
+    ) -> Option<PackedData>;
 }
 
 pub struct FaceRect {
@@ -170,6 +198,9 @@ pub struct RenderContext<'a> {
     pub block: Block,
     pub local_pos: USizeVec3,
     pub world_pos: IVec3,
+    /// A color the faces added while it's set are multiplied by, for biome colors or dyed blocks
+    /// that share a texture.
+    pub tint: Option<Color>,
 }
 
 impl RenderContext<'_> {
@@ -204,6 +235,33 @@ impl RenderContext<'_> {
             .map(|rect| rect.is_transparent)
             .unwrap_or(false);
 
+        if !is_transparent && !double_sided && self.mesh.is_mergeable_model(model_id) {
+            if !self.is_face_visible(face) {
+                return;
+            }
+
+            // Faces with uneven shading or light would lose it when stretched, so they're added as is
+            let shading = self.surface_shading(&surface);
+            let light = self.surface_light(&surface);
+
+            if shading.iter().all(|&corner| corner == shading[0])
+                && light.iter().all(|&corner| corner == light[0])
+            {
+                self.mesh.add_mergeable_face(
+                    self.local_pos,
+                    face,
+                    texture_index,
+                    shading[0],
+                    light[0],
+                    self.packed_tint(),
+                );
+            } else {
+                self.add_quad(surface, shading, light, texture_index, false, false);
+            }
+
+            return;
+        }
+
         self.add_surface(surface, texture_index, is_transparent, double_sided);
     }
 
@@ -228,67 +286,73 @@ impl RenderContext<'_> {
             return;
         }
 
-        let index = self.mesh.index();
-
-        let shading: [u32; 4] = [
-            self.sample_vertex_shading(surface.shading_offsets[0], surface.normal),
-            self.sample_vertex_shading(surface.shading_offsets[1], surface.normal),
-            self.sample_vertex_shading(surface.shading_offsets[2], surface.normal),
-            self.sample_vertex_shading(surface.shading_offsets[3], surface.normal),
-        ];
+        let shading = self.surface_shading(&surface);
+        let light = self.surface_light(&surface);
+        self.add_quad(
+            surface,
+            shading,
+            light,
+            texture_index,
+            is_transparent,
+            double_sided,
+        );
+    }
 
-        for (i, shading) in shading.into_iter().enumerate() {
-            self.mesh.vertices.push(ChunkVertex::new(
+    fn add_quad(
+        &mut self,
+        surface: BlockSurface,
+        shading: [u32; 4],
+        light: [Light; 4],
+        texture_index: u32,
+        is_transparent: bool,
+        double_sided: bool,
+    ) {
+        let tint = self.packed_tint();
+        let vertices = [0, 1, 2, 3].map(|i| {
+            ChunkVertex::new(
                 self.local_pos,
                 surface.vertex_indices[i],
-                shading,
+                shading[i],
                 texture_index,
                 is_transparent,
-            ));
-        }
+            )
+            .with_tint(tint)
+            .with_light(light[i])
+        });
 
-        if shading[0] + shading[2] < shading[1] + shading[3] {
-            self.mesh.indices.extend_from_slice(&[
-                index,
-                index + 1,
-                index + 3,
-                index + 1,
-                index + 2,
-                index + 3,
-            ]);
-            if double_sided {
-                self.mesh.indices.extend_from_slice(&[
-                    index + 3,
-                    index + 1,
-                    index,
-                    index + 3,
-                    index + 2,
-                    index + 1,
-                ]);
-            }
+        if is_transparent {
+            let center = surface
+                .vertex_indices
+                .into_iter()
+                .map(|index| Vec3::from(self.registry.model_vertex(index).position))
+                .sum::<Vec3>()
+                / 4.0;
+
+            self.mesh.add_transparent_quad(
+                vertices,
+                shading,
+                double_sided,
+                self.local_pos.as_vec3() + center,
+            );
         } else {
-            self.mesh.indices.extend_from_slice(&[
-                index,
-                index + 1,
-                index + 2,
-                index + 2,
-                index + 3,
-                index,
-            ]);
-            if double_sided {
-                self.mesh.indices.extend_from_slice(&[
-                    index,
-                    index + 3,
-                    index + 2,
-                    index + 2,
-                    index + 1,
-                    index,
-                ]);
-            }
+            self.mesh.add_quad(vertices, shading, double_sided);
         }
     }
 
-    fn sample_vertex_shading(&self, offset: IVec3, normal: IVec3) -> u32 {
+    fn packed_tint(&self) -> u32 {
+        self.tint
+            .map_or(ChunkVertex::NO_TINT, ChunkVertex::pack_tint)
+    }
+
+    fn surface_shading(&self, surface: &BlockSurface) -> [u32; 4] {
+        surface
+            .shading_offsets
+            .map(|offset| self.sample_vertex_shading(offset, surface.normal))
+    }
+
+    /// The blocks in front of a face that touch one of its corners: the two beside the corner
+    /// and the one diagonal to it.
+    fn corner_positions(&self, offset: IVec3, normal: IVec3) -> [IVec3; 3] {
         let (axis1, axis2) = if normal.x.abs() == 1 {
             (IVec3::Y, IVec3::Z)
         } else if normal.y.abs() == 1 {
@@ -300,9 +364,67 @@ impl RenderContext<'_> {
         let side1_dir = offset.dot(axis1).signum();
         let side2_dir = offset.dot(axis2).signum();
 
-        let side1_pos = self.world_pos + normal + axis1 * side1_dir;
-        let side2_pos = self.world_pos + normal + axis2 * side2_dir;
-        let corner_pos = self.world_pos + normal + axis1 * side1_dir + axis2 * side2_dir;
+        [
+            self.world_pos + normal + axis1 * side1_dir,
+            self.world_pos + normal + axis2 * side2_dir,
+            self.world_pos + normal + axis1 * side1_dir + axis2 * side2_dir,
+        ]
+    }
+
+    fn surface_light(&self, surface: &BlockSurface) -> [Light; 4] {
+        surface
+            .shading_offsets
+            .map(|offset| self.sample_vertex_light(offset, surface.normal))
+    }
+
+    /// Averages the light of the blocks around a corner of a face, leaving out opaque blocks
+    /// since they're always dark, so light fades smoothly across the face.
+    fn sample_vertex_light(&self, offset: IVec3, normal: IVec3) -> Light {
+        let is_opaque = |pos: IVec3| {
+            self.data.get_block(pos).is_some_and(|block| {
+                self.registry
+                    .block_type(block.id)
+                    .is_opaque_cube(block.data)
+            })
+        };
+
+        // Faces inside another block, like the inner faces of some models, take the block's own light
+        let front_pos = if is_opaque(self.world_pos + normal) {
+            self.world_pos
+        } else {
+            self.world_pos + normal
+        };
+        let [side1_pos, side2_pos, corner_pos] = self.corner_positions(offset, normal);
+        let side1 = !is_opaque(side1_pos);
+        let side2 = !is_opaque(side2_pos);
+
+        // The corner can't be seen past two opaque sides, like with ambient occlusion
+        let samples = [
+            (front_pos, true),
+            (side1_pos, side1),
+            (side2_pos, side2),
+            (corner_pos, (side1 || side2) && !is_opaque(corner_pos)),
+        ];
+
+        let (mut sky, mut block, mut count) = (0, 0, 0);
+
+        for (pos, is_lit) in samples {
+            if is_lit {
+                let light = self.data.get_light(pos);
+                sky += light.sky() as u32;
+                block += light.block() as u32;
+                count += 1;
+            }
+        }
+
+        Light::new(
+            (sky as f32 / count as f32).round() as u8,
+            (block as f32 / count as f32).round() as u8,
+        )
+    }
+
+    fn sample_vertex_shading(&self, offset: IVec3, normal: IVec3) -> u32 {
+        let [side1_pos, side2_pos, corner_pos] = self.corner_positions(offset, normal);
 
         let get_neighbor_face_toward_block = |neighbor_pos: IVec3| -> BlockFace {
             let diff = neighbor_pos - self.world_pos;
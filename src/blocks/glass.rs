@@ -1,8 +1,8 @@
 use bevy::math::Rect;
 
 use crate::{
-    Block, BlockFace, BlockType, FaceRect, ModelId, PackedData, Registry, RenderContext,
-    render_block_with_model,
+    Block, BlockFace, BlockType, FaceRect, MaterialId, ModelId, PackedData, Registry,
+    RenderContext, render_block_with_model,
 };
 
 pub struct Glass;
@@ -39,4 +39,12 @@ impl BlockType for Glass {
     fn render(&self, ctx: &mut RenderContext) {
         render_block_with_model(ctx, self.model_id(ctx.registry, ctx.block.data), true);
     }
+
+    fn map_materials(
+        &self,
+        data: PackedData,
+        _map: &dyn Fn(MaterialId) -> Option<MaterialId>,
+    ) -> Option<PackedData> {
+        Some(data)
+    }
 }
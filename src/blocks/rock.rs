@@ -1,4 +1,4 @@
-use crate::{Block, BlockType, PackedData, Registry, color_image};
+use crate::{Block, BlockType, MaterialId, PackedData, Registry, color_image};
 
 pub struct Rock;
 
@@ -28,4 +28,13 @@ impl BlockType for Rock {
             );
         }
     }
+
+    fn map_materials(
+        &self,
+        data: PackedData,
+        map: &dyn Fn(MaterialId) -> Option<MaterialId>,
+    ) -> Option<PackedData> {
+        let material = map(data.decode().take_material())?;
+        Some(PackedData::builder().with_material(material).build())
+    }
 }
@@ -1,7 +1,8 @@
 use bevy::prelude::*;
 
 use crate::{
-    Aabb, Block, BlockFace, BlockType, FaceRect, ModelId, PackedData, Registry, color_image,
+    Aabb, Block, BlockFace, BlockType, FaceRect, MaterialId, ModelId, PackedData, Registry,
+    color_image,
 };
 
 pub struct RockSlab;
@@ -58,4 +59,13 @@ impl BlockType for RockSlab {
     fn occludes_vertex_shading(&self, _face: BlockFace, _data: PackedData) -> bool {
         false
     }
+
+    fn map_materials(
+        &self,
+        data: PackedData,
+        map: &dyn Fn(MaterialId) -> Option<MaterialId>,
+    ) -> Option<PackedData> {
+        let material = map(data.decode().take_material())?;
+        Some(PackedData::builder().with_material(material).build())
+    }
 }
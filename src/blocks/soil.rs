@@ -1,5 +1,6 @@
 use crate::{
-    Block, BlockFace, BlockType, PackedData, Registry, RenderContext, color_image, overlay_image,
+    Block, BlockFace, BlockType, MaterialId, PackedData, Registry, RenderContext, color_image,
+    overlay_image,
 };
 
 pub struct Soil;
@@ -104,6 +105,27 @@ impl BlockType for Soil {
             ctx.add_model_face(model_id, face, texture, false);
         }
     }
+
+    fn map_materials(
+        &self,
+        data: PackedData,
+        map: &dyn Fn(MaterialId) -> Option<MaterialId>,
+    ) -> Option<PackedData> {
+        let mut data = data.decode();
+
+        let material = map(data.take_material())?;
+        let is_grass = data.take_bool();
+
+        let mut builder = PackedData::builder()
+            .with_material(material)
+            .with_bool(is_grass);
+
+        if is_grass {
+            builder = builder.with_material(map(data.take_material())?);
+        }
+
+        Some(builder.build())
+    }
 }
 
 impl Soil {
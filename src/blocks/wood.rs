@@ -1,4 +1,6 @@
-use crate::{Block, BlockFace, BlockType, PackedData, Registry, RenderContext, color_image};
+use crate::{
+    Block, BlockFace, BlockType, MaterialId, PackedData, Registry, RenderContext, color_image,
+};
 
 pub struct Wood;
 
@@ -78,4 +80,13 @@ impl BlockType for Wood {
             ctx.add_model_face(model_id, face, texture, false);
         }
     }
+
+    fn map_materials(
+        &self,
+        data: PackedData,
+        map: &dyn Fn(MaterialId) -> Option<MaterialId>,
+    ) -> Option<PackedData> {
+        let material = map(data.decode().take_material())?;
+        Some(PackedData::builder().with_material(material).build())
+    }
 }
@@ -1,24 +1,39 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use bevy::math::USizeVec3;
-use indexmap::IndexSet;
 
 use crate::Block;
 
 pub const CHUNK_SIZE: usize = 32;
 
+const CHUNK_VOLUME: usize = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
+
+// Unused palette entries are freed, so a chunk never needs more entries than it has blocks
+const _: () = assert!(CHUNK_VOLUME <= u16::MAX as usize + 1);
+
+/// Index of air in every palette, which is never freed.
+const AIR_INDEX: u16 = 0;
+
 pub type ChunkData = Arc<ChunkDataInner>;
 
 #[derive(Debug, Clone)]
 pub struct ChunkDataInner {
-    blocks: Vec<u16>,
-    // TODO: Clean up unused palette entries
-    palette: IndexSet<Block>,
+    blocks: BlockStorage,
+    palette: Vec<PaletteEntry>,
+    palette_map: HashMap<Block, u16>,
+    /// Entries with no blocks left, which are reused before the palette grows.
+    free_entries: Vec<u16>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PaletteEntry {
+    block: Option<Block>,
+    count: u32,
 }
 
 impl Default for ChunkDataInner {
     fn default() -> Self {
-        Self::from_data(vec![None; CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE])
+        Self::filled(None)
     }
 }
 
@@ -27,52 +42,242 @@ impl ChunkDataInner {
         Self::default()
     }
 
-    pub fn from_data(data: Vec<Option<Block>>) -> Self {
-        let mut blocks = vec![0; CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE];
-        let mut palette = IndexSet::new();
+    /// Creates a chunk where every block is the same, which takes no block storage.
+    pub fn filled(block: Option<Block>) -> Self {
+        let mut palette = vec![PaletteEntry {
+            block: None,
+            count: 0,
+        }];
+        let mut palette_map = HashMap::new();
 
-        for (i, block) in data.iter().enumerate() {
-            if let Some(block) = block {
-                palette.insert(*block);
-            }
+        if let Some(block) = block {
+            palette.push(PaletteEntry {
+                block: Some(block),
+                count: 0,
+            });
+            palette_map.insert(block, 1);
+        }
 
-            blocks[i] = block
-                .map(|block| palette.get_index_of(&block).unwrap() as u16)
-                .unwrap_or(u16::MAX);
+        let index = palette.len() as u16 - 1;
+        palette[index as usize].count = CHUNK_VOLUME as u32;
+
+        Self {
+            blocks: BlockStorage::Uniform(index),
+            palette,
+            palette_map,
+            free_entries: Vec::new(),
         }
+    }
+
+    pub fn from_data(data: Vec<Option<Block>>) -> Self {
+        assert_eq!(data.len(), CHUNK_VOLUME);
+
+        let mut chunk = Self {
+            blocks: BlockStorage::Uniform(AIR_INDEX),
+            palette: vec![PaletteEntry {
+                block: None,
+                count: 0,
+            }],
+            palette_map: HashMap::new(),
+            free_entries: Vec::new(),
+        };
 
-        Self { blocks, palette }
+        let indices = data
+            .into_iter()
+            .map(|block| chunk.acquire(block))
+            .collect::<Vec<_>>();
+
+        chunk.blocks = BlockStorage::pack(&indices, chunk.palette.len());
+        chunk
     }
 
     pub fn get_block(&self, local_pos: USizeVec3) -> Option<Block> {
-        let index = self.blocks[self.index(local_pos)];
-
-        if index == u16::MAX {
-            None
-        } else {
-            Some(self.palette[index as usize])
-        }
+        self.palette[self.blocks.get(self.index(local_pos)) as usize].block
     }
 
     pub fn set_block(&mut self, local_pos: USizeVec3, block: Option<Block>) {
         let index = self.index(local_pos);
+        let old_index = self.blocks.get(index);
 
-        if let Some(block) = block {
-            self.palette.insert(block);
-            self.blocks[index] = self.palette.get_index_of(&block).unwrap() as u16;
+        if self.palette[old_index as usize].block == block {
+            return;
+        }
+
+        // Releasing first lets the new block take over the old entry if it was the last use
+        self.release(old_index);
+        let new_index = self.acquire(block);
+
+        if self.palette[new_index as usize].count as usize == CHUNK_VOLUME {
+            self.blocks = BlockStorage::Uniform(new_index);
         } else {
-            self.blocks[index] = u16::MAX;
+            self.blocks.set(index, new_index, self.palette.len());
         }
+
+        // Reusing free entries keeps the palette bounded, but it can still be left mostly empty
+        // after large edits
+        if self.free_entries.len() > 16 && self.free_entries.len() * 2 > self.palette.len() {
+            self.compact();
+        }
+    }
+
+    /// Whether every block in the chunk is air.
+    pub fn is_empty(&self) -> bool {
+        self.palette[AIR_INDEX as usize].count as usize == CHUNK_VOLUME
+    }
+
+    /// Whether every block in the chunk is the same, including when it's empty.
+    pub fn is_uniform(&self) -> bool {
+        matches!(self.blocks, BlockStorage::Uniform(_))
     }
 
     pub fn iter(&self) -> impl Iterator<Item = Option<Block>> {
-        self.blocks.iter().map(|index| {
-            if *index == u16::MAX {
-                None
-            } else {
-                Some(self.palette[*index as usize])
+        (0..CHUNK_VOLUME).map(|index| self.palette[self.blocks.get(index) as usize].block)
+    }
+
+    /// The number of bytes used to store the blocks, not counting the palette.
+    pub fn storage_size(&self) -> usize {
+        match &self.blocks {
+            BlockStorage::Uniform(_) => 0,
+            BlockStorage::Packed { words, .. } => words.len() * size_of::<u64>(),
+        }
+    }
+
+    /// The number of palette entries in use, including air.
+    pub fn palette_len(&self) -> usize {
+        self.palette.len() - self.free_entries.len()
+    }
+
+    /// Removes unused palette entries, renumbering the rest, and shrinks the block storage to fit.
+    pub fn compact(&mut self) {
+        if self.free_entries.is_empty() {
+            return;
+        }
+
+        let mut remap = vec![AIR_INDEX; self.palette.len()];
+        let mut palette = Vec::with_capacity(self.palette_len());
+
+        for (old_index, entry) in self.palette.iter().enumerate() {
+            if entry.count == 0 && old_index != AIR_INDEX as usize {
+                continue;
+            }
+
+            remap[old_index] = palette.len() as u16;
+
+            if let Some(block) = entry.block {
+                self.palette_map.insert(block, palette.len() as u16);
+            }
+
+            palette.push(*entry);
+        }
+
+        self.blocks = self
+            .blocks
+            .repack(bits_for(palette.len()), |index| remap[index as usize]);
+        self.palette = palette;
+        self.free_entries.clear();
+    }
+
+    /// Shrinks the chunk by `scale` along each axis for low detail meshes of distant chunks. The
+    /// result fills the low corner of the chunk, with air everywhere else.
+    ///
+    /// Cells that are at least half solid become their most common block, with ties going to
+    /// blocks nearer the top of the cell so surfaces keep their look.
+    pub fn downsample(&self, scale: usize) -> Self {
+        if self.is_empty() {
+            return Self::filled(None);
+        }
+
+        let size = CHUNK_SIZE / scale;
+        let mut data = vec![None; CHUNK_VOLUME];
+        let mut counts: Vec<(Block, usize)> = Vec::new();
+
+        for x in 0..size {
+            for y in 0..size {
+                for z in 0..size {
+                    counts.clear();
+                    let mut solid = 0;
+
+                    for offset_y in (0..scale).rev() {
+                        for offset_x in 0..scale {
+                            for offset_z in 0..scale {
+                                let local_pos = USizeVec3::new(
+                                    x * scale + offset_x,
+                                    y * scale + offset_y,
+                                    z * scale + offset_z,
+                                );
+
+                                let Some(block) = self.get_block(local_pos) else {
+                                    continue;
+                                };
+
+                                solid += 1;
+
+                                match counts.iter_mut().find(|(counted, _)| *counted == block) {
+                                    Some((_, count)) => *count += 1,
+                                    None => counts.push((block, 1)),
+                                }
+                            }
+                        }
+                    }
+
+                    if solid * 2 >= scale * scale * scale {
+                        // `max_by_key` picks the last of equal counts, so search in reverse to prefer the
+                        // blocks found first
+                        data[self.index(USizeVec3::new(x, y, z))] = counts
+                            .iter()
+                            .rev()
+                            .max_by_key(|(_, count)| *count)
+                            .map(|(block, _)| *block);
+                    }
+                }
             }
-        })
+        }
+
+        Self::from_data(data)
+    }
+
+    /// Returns the palette index for a block, adding it if needed, and counts one more use of it.
+    fn acquire(&mut self, block: Option<Block>) -> u16 {
+        let index = match block {
+            None => AIR_INDEX,
+            Some(block) => match self.palette_map.get(&block) {
+                Some(index) => *index,
+                None => {
+                    let entry = PaletteEntry {
+                        block: Some(block),
+                        count: 0,
+                    };
+
+                    let index = if let Some(index) = self.free_entries.pop() {
+                        self.palette[index as usize] = entry;
+                        index
+                    } else {
+                        self.palette.push(entry);
+                        (self.palette.len() - 1) as u16
+                    };
+
+                    self.palette_map.insert(block, index);
+                    index
+                }
+            },
+        };
+
+        self.palette[index as usize].count += 1;
+        index
+    }
+
+    /// Counts one less use of a palette entry, freeing it once nothing uses it.
+    fn release(&mut self, index: u16) {
+        let entry = &mut self.palette[index as usize];
+        entry.count -= 1;
+
+        if entry.count == 0
+            && index != AIR_INDEX
+            && let Some(block) = entry.block
+        {
+            self.palette_map.remove(&block);
+            self.free_entries.push(index);
+        }
     }
 
     fn index(&self, local_pos: USizeVec3) -> usize {
@@ -82,3 +287,232 @@ impl ChunkDataInner {
         local_pos.x + local_pos.y * CHUNK_SIZE + local_pos.z * CHUNK_SIZE * CHUNK_SIZE
     }
 }
+
+/// Palette indices for every block in a chunk, packed into as few bits as the palette allows.
+#[derive(Debug, Clone)]
+enum BlockStorage {
+    /// Every block uses the same palette entry, which takes no storage at all.
+    Uniform(u16),
+    /// Indices of `bits` bits each, which never straddle two words.
+    Packed { bits: u32, words: Vec<u64> },
+}
+
+/// Returns the smallest supported bit width that can index a palette of `len` entries.
+fn bits_for(len: usize) -> u32 {
+    match len {
+        0..=2 => 1,
+        3..=4 => 2,
+        5..=16 => 4,
+        17..=256 => 8,
+        _ => 16,
+    }
+}
+
+impl BlockStorage {
+    fn pack(indices: &[u16], palette_len: usize) -> Self {
+        if indices.iter().all(|index| *index == indices[0]) {
+            return Self::Uniform(indices[0]);
+        }
+
+        let mut storage = Self::filled(bits_for(palette_len), 0);
+
+        for (i, index) in indices.iter().enumerate() {
+            storage.set(i, *index, palette_len);
+        }
+
+        storage
+    }
+
+    fn filled(bits: u32, index: u16) -> Self {
+        let per_word = 64 / bits as usize;
+        let mut word = 0;
+
+        for i in 0..per_word {
+            word |= (index as u64) << (i * bits as usize);
+        }
+
+        Self::Packed {
+            bits,
+            words: vec![word; CHUNK_VOLUME.div_ceil(per_word)],
+        }
+    }
+
+    fn get(&self, i: usize) -> u16 {
+        match self {
+            Self::Uniform(index) => *index,
+            Self::Packed { bits, words } => {
+                let bit = i * *bits as usize;
+                ((words[bit / 64] >> (bit % 64)) & ((1 << bits) - 1)) as u16
+            }
+        }
+    }
+
+    /// Sets the index of one block, widening the storage first if the index doesn't fit.
+    fn set(&mut self, i: usize, index: u16, palette_len: usize) {
+        match self {
+            Self::Uniform(uniform_index) if *uniform_index == index => return,
+            Self::Uniform(uniform_index) => {
+                *self = Self::filled(bits_for(palette_len), *uniform_index);
+            }
+            Self::Packed { bits, .. } if (index as u32) >> *bits != 0 => {
+                *self = self.repack(bits_for(palette_len), |index| index);
+            }
+            Self::Packed { .. } => {}
+        }
+
+        let Self::Packed { bits, words } = self else {
+            unreachable!();
+        };
+
+        let bit = i * *bits as usize;
+        let mask = ((1u64 << *bits) - 1) << (bit % 64);
+        let word = &mut words[bit / 64];
+        *word = (*word & !mask) | ((index as u64) << (bit % 64));
+    }
+
+    /// Rewrites every index through `remap` into storage with the given bit width.
+    fn repack(&self, bits: u32, remap: impl Fn(u16) -> u16) -> Self {
+        match self {
+            Self::Uniform(index) => Self::Uniform(remap(*index)),
+            Self::Packed { .. } => {
+                let mut storage = Self::filled(bits, 0);
+
+                for i in 0..CHUNK_VOLUME {
+                    storage.set(i, remap(self.get(i)), 1 << bits);
+                }
+
+                storage
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{BlockId, PackedData};
+
+    fn block(id: u16) -> Option<Block> {
+        Some(Block::new(BlockId(id), PackedData::builder().build()))
+    }
+
+    #[test]
+    fn test_palette_garbage_collection() {
+        let mut chunk = ChunkDataInner::new();
+        let pos = USizeVec3::new(1, 2, 3);
+
+        // Replacing the only use of a block frees its entry, which is then reused
+        chunk.set_block(pos, block(1));
+        chunk.set_block(pos, block(2));
+        assert_eq!(chunk.palette_len(), 2);
+        assert_eq!(chunk.palette.len(), 2);
+        assert_eq!(chunk.get_block(pos), block(2));
+
+        // Far more distinct blocks than fit in a u16 over the lifetime of the chunk
+        for id in 0..100_000u32 {
+            let pos = USizeVec3::new(id as usize % CHUNK_SIZE, 0, 0);
+            chunk.set_block(pos, block(id as u16 ^ (id >> 16) as u16));
+        }
+
+        assert!(chunk.palette_len() <= CHUNK_SIZE + 2);
+
+        // Clearing the blocks compacts the palette again
+        for x in 0..CHUNK_SIZE {
+            chunk.set_block(USizeVec3::new(x, 0, 0), None);
+        }
+
+        assert_eq!(chunk.palette_len(), 2);
+        assert!(chunk.palette.len() <= 2 + 16);
+        assert_eq!(chunk.get_block(pos), block(2));
+        assert_eq!(chunk.iter().filter(Option::is_some).count(), 1);
+
+        chunk.compact();
+        assert_eq!(chunk.palette.len(), 2);
+        assert_eq!(chunk.get_block(pos), block(2));
+    }
+
+    #[test]
+    fn test_packed_storage() {
+        let mut chunk = ChunkDataInner::new();
+        assert_eq!(chunk.storage_size(), 0);
+
+        // The storage widens as the palette grows
+        let mut expected = vec![None; CHUNK_VOLUME];
+
+        for (id, size) in [
+            (1, 4096),
+            (3, 8192),
+            (15, 16384),
+            (255, 32768),
+            (300, 65536),
+        ] {
+            for id in 1..=id {
+                let pos = USizeVec3::new(id as usize % CHUNK_SIZE, id as usize / CHUNK_SIZE, 5);
+                chunk.set_block(pos, block(id));
+                expected[chunk.index(pos)] = block(id);
+            }
+
+            assert_eq!(chunk.storage_size(), size);
+            assert!(chunk.iter().eq(expected.iter().copied()));
+        }
+
+        // Filling the whole chunk with a single block makes it uniform again
+        for (i, expected) in expected.iter_mut().enumerate() {
+            let pos = USizeVec3::new(
+                i % CHUNK_SIZE,
+                i / CHUNK_SIZE % CHUNK_SIZE,
+                i / (CHUNK_SIZE * CHUNK_SIZE),
+            );
+            chunk.set_block(pos, block(7));
+            *expected = block(7);
+        }
+
+        assert_eq!(chunk.storage_size(), 0);
+        assert_eq!(chunk.palette_len(), 2);
+        assert!(chunk.is_uniform() && !chunk.is_empty());
+        assert!(chunk.iter().eq(expected.iter().copied()));
+
+        // Mixed chunks built from data use the narrowest width
+        expected[0] = None;
+        let mut chunk = ChunkDataInner::from_data(expected.clone());
+        assert_eq!(chunk.storage_size(), 4096);
+        assert!(!chunk.is_uniform() && !chunk.is_empty());
+        assert!(chunk.iter().eq(expected.into_iter()));
+
+        chunk.set_block(USizeVec3::ZERO, block(7));
+        assert!(chunk.is_uniform());
+        assert_eq!(chunk.iter().count(), CHUNK_VOLUME);
+    }
+
+    #[test]
+    fn test_downsample() {
+        let mut chunk = ChunkDataInner::new();
+
+        // A 2x2x2 cell with a layer of block 1 on top of block 2 ties, so the top layer wins
+        for x in 0..2 {
+            for z in 0..2 {
+                chunk.set_block(USizeVec3::new(x, 0, z), block(2));
+                chunk.set_block(USizeVec3::new(x, 1, z), block(1));
+            }
+        }
+
+        // Cells less than half full become air
+        chunk.set_block(USizeVec3::new(2, 0, 0), block(3));
+
+        let downsampled = chunk.downsample(2);
+        assert_eq!(downsampled.get_block(USizeVec3::ZERO), block(1));
+        assert_eq!(downsampled.get_block(USizeVec3::new(1, 0, 0)), None);
+        assert_eq!(downsampled.iter().filter(Option::is_some).count(), 1);
+
+        // Everything outside the low corner is air
+        let downsampled = ChunkDataInner::filled(block(4)).downsample(8);
+        let size = CHUNK_SIZE / 8;
+        assert_eq!(
+            downsampled.iter().filter(Option::is_some).count(),
+            size * size * size
+        );
+        assert_eq!(downsampled.get_block(USizeVec3::splat(size - 1)), block(4));
+        assert_eq!(downsampled.get_block(USizeVec3::splat(size)), None);
+    }
+}
@@ -18,6 +18,10 @@ pub struct ChunkMaterial {
     pub ao_factor: f32,
     #[storage(3, read_only)]
     pub model_buffer: Handle<ShaderStorageBuffer>,
+    /// Pixels less opaque than this are discarded, which cuts out leaves and similar textures.
+    #[uniform(4)]
+    pub alpha_cutoff: f32,
+    pub alpha_mode: AlphaMode,
 }
 
 pub const ATTRIBUTE_PACKED_DATA: MeshVertexAttribute =
@@ -26,6 +30,18 @@ pub const ATTRIBUTE_PACKED_DATA: MeshVertexAttribute =
 pub const ATTRIBUTE_TEXTURE_INDEX: MeshVertexAttribute =
     MeshVertexAttribute::new("TextureIndex", 47198479, VertexFormat::Uint32);
 
+/// How many blocks a quad stretches over along each axis, see `ChunkVertex::with_size`.
+pub const ATTRIBUTE_QUAD_SIZE: MeshVertexAttribute =
+    MeshVertexAttribute::new("QuadSize", 47198480, VertexFormat::Uint32);
+
+/// The color a face's texture is multiplied by, see `ChunkVertex::tint`.
+pub const ATTRIBUTE_TINT: MeshVertexAttribute =
+    MeshVertexAttribute::new("Tint", 47198481, VertexFormat::Uint32);
+
+/// The sky and block light at a vertex, see `ChunkVertex::light`.
+pub const ATTRIBUTE_LIGHT: MeshVertexAttribute =
+    MeshVertexAttribute::new("Light", 47198482, VertexFormat::Uint32);
+
 impl Material for ChunkMaterial {
     fn vertex_shader() -> ShaderRef {
         "shader.wgsl".into()
@@ -36,7 +52,7 @@ impl Material for ChunkMaterial {
     }
 
     fn alpha_mode(&self) -> AlphaMode {
-        AlphaMode::AlphaToCoverage
+        self.alpha_mode
     }
 
     fn specialize(
@@ -48,6 +64,9 @@ impl Material for ChunkMaterial {
         let vertex_layout = layout.0.get_layout(&[
             ATTRIBUTE_PACKED_DATA.at_shader_location(0),
             ATTRIBUTE_TEXTURE_INDEX.at_shader_location(1),
+            ATTRIBUTE_QUAD_SIZE.at_shader_location(2),
+            ATTRIBUTE_TINT.at_shader_location(3),
+            ATTRIBUTE_LIGHT.at_shader_location(4),
         ])?;
         descriptor.vertex.buffers = vec![vertex_layout];
         Ok(())
@@ -1,3 +1,5 @@
+use std::ops::Range;
+
 use bevy::{
     asset::RenderAssetUsages,
     math::USizeVec3,
@@ -6,22 +8,34 @@ use bevy::{
 };
 
 use crate::{
-    ATTRIBUTE_PACKED_DATA, ATTRIBUTE_TEXTURE_INDEX, CHUNK_SIZE, Registry, RelevantChunks,
-    RenderContext,
+    ATTRIBUTE_LIGHT, ATTRIBUTE_PACKED_DATA, ATTRIBUTE_QUAD_SIZE, ATTRIBUTE_TEXTURE_INDEX,
+    ATTRIBUTE_TINT, BlockFace, CHUNK_SIZE, Light, ModelId, Registry, RelevantChunks, RenderContext,
 };
 
+/// Builds the mesh for a chunk. With `greedy_meshing`, neighboring faces of full cubes that look
+/// the same are merged into larger quads.
+///
+/// Downsampled chunks are meshed in their own blocks, which are scaled back up to the size of the
+/// chunk.
 pub fn generate_mesh(
     center_pos: IVec3,
     data: &RelevantChunks,
     registry: &Registry,
-) -> Option<Mesh> {
-    let mut mesh = ChunkMeshBuilder::new();
+    greedy_meshing: bool,
+) -> ChunkMeshes {
+    let mut mesh = if greedy_meshing {
+        ChunkMeshBuilder::with_greedy_meshing(registry)
+    } else {
+        ChunkMeshBuilder::new()
+    };
+    mesh.scale = data.scale();
+
+    let size = CHUNK_SIZE / data.scale();
 
-    for x in 0..CHUNK_SIZE {
-        for y in 0..CHUNK_SIZE {
-            for z in 0..CHUNK_SIZE {
-                let world_pos =
-                    center_pos * CHUNK_SIZE as i32 + IVec3::new(x as i32, y as i32, z as i32);
+    for x in 0..size {
+        for y in 0..size {
+            for z in 0..size {
+                let world_pos = center_pos * size as i32 + IVec3::new(x as i32, y as i32, z as i32);
 
                 let Some(block) = data.get_block(world_pos) else {
                     continue;
@@ -34,6 +48,7 @@ pub fn generate_mesh(
                     mesh: &mut mesh,
                     block,
                     registry,
+                    tint: None,
                 });
             }
         }
@@ -42,10 +57,41 @@ pub fn generate_mesh(
     mesh.build()
 }
 
+/// The meshes of a chunk. Transparent faces are kept apart so they can be blended and drawn back
+/// to front after everything opaque.
 #[derive(Debug, Default)]
+pub struct ChunkMeshes {
+    pub opaque: Option<Mesh>,
+    pub transparent: Option<(Mesh, TransparentQuads)>,
+}
+
+impl ChunkMeshes {
+    pub fn is_empty(&self) -> bool {
+        self.opaque.is_none() && self.transparent.is_none()
+    }
+}
+
+#[derive(Debug)]
 pub struct ChunkMeshBuilder {
-    pub vertices: Vec<ChunkVertex>,
-    pub indices: Vec<u32>,
+    pub opaque: MeshBuffers,
+    pub transparent: MeshBuffers,
+    /// The center of each transparent quad and the range of its indices, used to sort them.
+    transparent_quads: Vec<(Vec3, Range<usize>)>,
+    /// How many blocks each quad added to the builder is scaled up by.
+    pub scale: usize,
+    greedy_faces: Option<GreedyFaces>,
+}
+
+impl Default for ChunkMeshBuilder {
+    fn default() -> Self {
+        Self {
+            opaque: MeshBuffers::default(),
+            transparent: MeshBuffers::default(),
+            transparent_quads: Vec::new(),
+            scale: 1,
+            greedy_faces: None,
+        }
+    }
 }
 
 impl ChunkMeshBuilder {
@@ -53,10 +99,154 @@ impl ChunkMeshBuilder {
         Self::default()
     }
 
+    pub fn with_greedy_meshing(registry: &Registry) -> Self {
+        let cube_model = registry.model_id("cube");
+
+        Self {
+            greedy_faces: Some(GreedyFaces {
+                cube_model,
+                cube_offset: registry.model_offset(cube_model),
+                faces: Default::default(),
+            }),
+            ..Self::default()
+        }
+    }
+
+    /// Whether faces of `model_id` are collected for merging rather than added straight away.
+    pub fn is_mergeable_model(&self, model_id: ModelId) -> bool {
+        self.greedy_faces
+            .as_ref()
+            .is_some_and(|greedy_faces| greedy_faces.cube_model == model_id)
+    }
+
+    /// Queues a visible, opaque cube face with the same shading at every corner for merging.
+    pub fn add_mergeable_face(
+        &mut self,
+        local_pos: USizeVec3,
+        face: BlockFace,
+        texture_index: u32,
+        shading: u32,
+        light: Light,
+        tint: u32,
+    ) {
+        let greedy_faces = self
+            .greedy_faces
+            .as_mut()
+            .expect("greedy meshing should be enabled");
+
+        let faces = &mut greedy_faces.faces[face as usize];
+
+        if faces.is_empty() {
+            faces.resize(CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE, NO_FACE);
+        }
+
+        faces[grid_index([local_pos.x, local_pos.y, local_pos.z])] = ((tint as u64) << 40)
+            | ((light.packed() as u64) << 32)
+            | ((texture_index << 2) | shading) as u64;
+    }
+
+    /// Adds an opaque quad, splitting it along the diagonal that keeps the shading smooth.
+    pub fn add_quad(&mut self, vertices: [ChunkVertex; 4], shading: [u32; 4], double_sided: bool) {
+        let vertices = vertices.map(|vertex| vertex.scaled(self.scale));
+        self.opaque.add_quad(vertices, shading, double_sided);
+    }
+
+    /// Adds a transparent quad, with `center` in blocks from the corner of the chunk.
+    pub fn add_transparent_quad(
+        &mut self,
+        vertices: [ChunkVertex; 4],
+        shading: [u32; 4],
+        double_sided: bool,
+        center: Vec3,
+    ) {
+        let vertices = vertices.map(|vertex| vertex.scaled(self.scale));
+        let start = self.transparent.indices.len();
+        self.transparent.add_quad(vertices, shading, double_sided);
+
+        self.transparent_quads.push((
+            center * self.scale as f32,
+            start..self.transparent.indices.len(),
+        ));
+    }
+
+    pub fn build(mut self) -> ChunkMeshes {
+        if let Some(greedy_faces) = self.greedy_faces.take() {
+            greedy_faces.merge(&mut self);
+        }
+
+        let transparent_quads = TransparentQuads {
+            quads: self.transparent_quads,
+            indices: self.transparent.indices.clone(),
+        };
+
+        ChunkMeshes {
+            opaque: self.opaque.build(),
+            transparent: self
+                .transparent
+                .build()
+                .map(|mesh| (mesh, transparent_quads)),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MeshBuffers {
+    pub vertices: Vec<ChunkVertex>,
+    pub indices: Vec<u32>,
+}
+
+impl MeshBuffers {
     pub fn index(&self) -> u32 {
         self.vertices.len() as u32
     }
 
+    /// Adds a quad, splitting it along the diagonal that keeps the shading smooth.
+    pub fn add_quad(&mut self, vertices: [ChunkVertex; 4], shading: [u32; 4], double_sided: bool) {
+        let index = self.index();
+
+        self.vertices.extend_from_slice(&vertices);
+
+        if shading[0] + shading[2] < shading[1] + shading[3] {
+            self.indices.extend_from_slice(&[
+                index,
+                index + 1,
+                index + 3,
+                index + 1,
+                index + 2,
+                index + 3,
+            ]);
+            if double_sided {
+                self.indices.extend_from_slice(&[
+                    index + 3,
+                    index + 1,
+                    index,
+                    index + 3,
+                    index + 2,
+                    index + 1,
+                ]);
+            }
+        } else {
+            self.indices.extend_from_slice(&[
+                index,
+                index + 1,
+                index + 2,
+                index + 2,
+                index + 3,
+                index,
+            ]);
+            if double_sided {
+                self.indices.extend_from_slice(&[
+                    index,
+                    index + 3,
+                    index + 2,
+                    index + 2,
+                    index + 1,
+                    index,
+                ]);
+            }
+        }
+    }
+
     pub fn build(self) -> Option<Mesh> {
         if self.indices.is_empty() {
             return None;
@@ -64,10 +254,16 @@ impl ChunkMeshBuilder {
 
         let mut packed_data = Vec::new();
         let mut texture_indices = Vec::new();
+        let mut quad_sizes = Vec::new();
+        let mut tints = Vec::new();
+        let mut lights = Vec::new();
 
         for vertex in self.vertices {
             packed_data.push(vertex.data);
             texture_indices.push(vertex.texture_index);
+            quad_sizes.push(vertex.size);
+            tints.push(vertex.tint);
+            lights.push(vertex.light);
         }
 
         let mesh = Mesh::new(
@@ -76,19 +272,161 @@ impl ChunkMeshBuilder {
         )
         .with_inserted_attribute(ATTRIBUTE_PACKED_DATA, packed_data)
         .with_inserted_attribute(ATTRIBUTE_TEXTURE_INDEX, texture_indices)
+        .with_inserted_attribute(ATTRIBUTE_QUAD_SIZE, quad_sizes)
+        .with_inserted_attribute(ATTRIBUTE_TINT, tints)
+        .with_inserted_attribute(ATTRIBUTE_LIGHT, lights)
         .with_inserted_indices(Indices::U32(self.indices));
 
         Some(mesh)
     }
 }
 
+/// The transparent quads of a chunk, which are re-sorted back to front as the camera moves so
+/// they blend correctly.
+#[derive(Component, Debug, Clone)]
+pub struct TransparentQuads {
+    /// The center of each quad relative to the chunk, and the range of its indices.
+    quads: Vec<(Vec3, Range<usize>)>,
+    indices: Vec<u32>,
+}
+
+impl TransparentQuads {
+    /// The indices with the quads ordered furthest first from `view_pos`, relative to the chunk.
+    pub fn sorted_indices(&self, view_pos: Vec3) -> Vec<u32> {
+        let mut quads = self.quads.iter().collect::<Vec<_>>();
+
+        quads.sort_by(|(a, _), (b, _)| {
+            b.distance_squared(view_pos)
+                .total_cmp(&a.distance_squared(view_pos))
+        });
+
+        quads
+            .into_iter()
+            .flat_map(|(_, range)| self.indices[range.clone()].iter().copied())
+            .collect()
+    }
+}
+
+const NO_FACE: u64 = u64::MAX;
+
+fn grid_index(pos: [usize; 3]) -> usize {
+    (pos[0] * CHUNK_SIZE + pos[1]) * CHUNK_SIZE + pos[2]
+}
+
+/// Cube faces waiting to be merged, with one grid per face direction. Each cell holds the tint,
+/// light, texture index and shading of the face packed together, so faces can only merge if all
+/// match.
+#[derive(Debug)]
+struct GreedyFaces {
+    cube_model: ModelId,
+    cube_offset: u32,
+    faces: [Vec<u64>; 6],
+}
+
+impl GreedyFaces {
+    fn merge(mut self, mesh: &mut ChunkMeshBuilder) {
+        for face in BlockFace::ALL {
+            let mut faces = std::mem::take(&mut self.faces[face as usize]);
+
+            if faces.is_empty() {
+                continue;
+            }
+
+            let normal_axis = face.normal().abs().max_position();
+            let u_axis = (normal_axis + 1) % 3;
+            let v_axis = (normal_axis + 2) % 3;
+
+            let grid_pos = |layer: usize, u: usize, v: usize| {
+                let mut pos = [0; 3];
+                pos[normal_axis] = layer;
+                pos[u_axis] = u;
+                pos[v_axis] = v;
+                pos
+            };
+
+            let start = self.cube_offset + face.model_vertex_start();
+
+            for layer in 0..CHUNK_SIZE {
+                for v in 0..CHUNK_SIZE {
+                    for u in 0..CHUNK_SIZE {
+                        let key = faces[grid_index(grid_pos(layer, u, v))];
+
+                        if key == NO_FACE {
+                            continue;
+                        }
+
+                        // Grow along u as far as possible, then along v while whole rows match
+                        let mut width = 1;
+
+                        while u + width < CHUNK_SIZE
+                            && faces[grid_index(grid_pos(layer, u + width, v))] == key
+                        {
+                            width += 1;
+                        }
+
+                        let mut height = 1;
+
+                        while v + height < CHUNK_SIZE
+                            && (u..u + width).all(|row_u| {
+                                faces[grid_index(grid_pos(layer, row_u, v + height))] == key
+                            })
+                        {
+                            height += 1;
+                        }
+
+                        for merged_v in v..v + height {
+                            for merged_u in u..u + width {
+                                faces[grid_index(grid_pos(layer, merged_u, merged_v))] = NO_FACE;
+                            }
+                        }
+
+                        let [x, y, z] = grid_pos(layer, u, v);
+                        let mut size = [1; 3];
+                        size[u_axis] = width;
+                        size[v_axis] = height;
+
+                        let tint = (key >> 40) as u32;
+                        let light = (key >> 32) as u32 & 0xFF;
+                        let texture_index = (key as u32) >> 2;
+                        let shading = key as u32 & 0b11;
+                        let vertices = [0, 1, 2, 3].map(|i| {
+                            ChunkVertex::new(
+                                USizeVec3::new(x, y, z),
+                                start + i,
+                                shading,
+                                texture_index,
+                                false,
+                            )
+                            .with_size(USizeVec3::from_array(size))
+                            .with_tint(tint)
+                            .with_packed_light(light)
+                        });
+
+                        mesh.add_quad(vertices, [shading; 4], false);
+                    }
+                }
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct ChunkVertex {
     pub data: u32,
     pub texture_index: u32,
+    /// How many blocks the quad covers along each axis, minus one, with 5 bits per axis (X in
+    /// bits 10-14, Y in bits 5-9, Z in bits 0-4).
+    pub size: u32,
+    /// An sRGB color the texture is multiplied by, as `0xRRGGBB`.
+    pub tint: u32,
+    /// The sky light in bits 4-7 and block light in bits 0-3, see `Light`.
+    pub light: u32,
 }
 
 impl ChunkVertex {
+    /// The tint that leaves the texture as is.
+    pub const NO_TINT: u32 = 0xFFFFFF;
+
     /// Creates a new chunk vertex with bitpacked data
     /// Packing layout (30 bits total in u32):
     /// - Position X: 5 bits (bits 25-29)
@@ -106,6 +444,188 @@ impl ChunkVertex {
                 | (ao << 1)
                 | (is_transparent as u32),
             texture_index,
+            size: 0,
+            tint: Self::NO_TINT,
+            light: Light::new(Light::MAX, 0).packed() as u32,
+        }
+    }
+
+    /// Moves and stretches the vertex by `scale`, so it lines up with blocks `scale` times smaller.
+    pub fn scaled(self, scale: usize) -> Self {
+        let scale = scale as u32;
+        let position = |shift: u32| ((self.data >> shift) & 0x1F) * scale;
+        let size = |shift: u32| ((self.size >> shift) & 0x1F) + 1;
+
+        Self {
+            data: (position(25) << 25)
+                | (position(20) << 20)
+                | (position(15) << 15)
+                | (self.data & 0x7FFF),
+            size: ((size(10) * scale - 1) << 10)
+                | ((size(5) * scale - 1) << 5)
+                | (size(0) * scale - 1),
+            ..self
         }
     }
+
+    /// Stretches the vertex's face over `size` blocks, for quads that cover several faces.
+    pub fn with_size(mut self, size: USizeVec3) -> Self {
+        self.size =
+            (((size.x - 1) as u32) << 10) | (((size.y - 1) as u32) << 5) | (size.z - 1) as u32;
+        self
+    }
+
+    pub fn with_tint(mut self, tint: u32) -> Self {
+        self.tint = tint;
+        self
+    }
+
+    pub fn with_light(self, light: Light) -> Self {
+        self.with_packed_light(light.packed() as u32)
+    }
+
+    fn with_packed_light(mut self, light: u32) -> Self {
+        self.light = light;
+        self
+    }
+
+    /// Packs a color into a tint, dropping its alpha.
+    pub fn pack_tint(color: Color) -> u32 {
+        let [r, g, b, _] = color.to_srgba().to_u8_array();
+        u32::from_be_bytes([0, r, g, b])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use bevy::mesh::VertexAttributeValues;
+
+    use super::*;
+
+    use crate::{
+        Block, BlockType, ChunkDataInner, MaterialId, PackedData, render_block_with_model,
+    };
+
+    const WEST_TINT: Color = Color::srgb(1.0, 0.0, 0.0);
+    const EAST_TINT: Color = Color::srgb(0.0, 0.0, 1.0);
+
+    /// A block with a different tint in the west and east halves of the chunk.
+    struct HalfTinted;
+
+    impl BlockType for HalfTinted {
+        fn unique_name(&self) -> String {
+            "half_tinted".into()
+        }
+
+        fn register(&self, registry: &mut Registry) {
+            let block = Block::new(
+                registry.block_id(&self.unique_name()),
+                PackedData::builder().build(),
+            );
+            registry.register_texture(block, 0);
+        }
+
+        fn render(&self, ctx: &mut RenderContext) {
+            ctx.tint = Some(if ctx.local_pos.x < CHUNK_SIZE / 2 {
+                WEST_TINT
+            } else {
+                EAST_TINT
+            });
+            render_block_with_model(ctx, self.model_id(ctx.registry, ctx.block.data), false);
+        }
+
+        fn map_materials(
+            &self,
+            data: PackedData,
+            _map: &dyn Fn(MaterialId) -> Option<MaterialId>,
+        ) -> Option<PackedData> {
+            Some(data)
+        }
+    }
+
+    fn mesh_tints(mesh: &Mesh) -> Vec<u32> {
+        match mesh.attribute(ATTRIBUTE_TINT) {
+            Some(VertexAttributeValues::Uint32(tints)) => tints.clone(),
+            _ => panic!("the mesh has no tints"),
+        }
+    }
+
+    #[test]
+    fn test_greedy_meshing() {
+        let mut registry = Registry::new();
+        registry.register_defaults();
+
+        let shale = registry.material_id("shale");
+        let rock = Block::new(
+            registry.block_id("rock"),
+            PackedData::builder().with_material(shale).build(),
+        );
+        let data = RelevantChunks::new(HashMap::from([(
+            IVec3::ZERO,
+            Arc::new(ChunkDataInner::filled(Some(rock))),
+        )]));
+
+        let faces = 6 * CHUNK_SIZE * CHUNK_SIZE;
+        let meshes = generate_mesh(IVec3::ZERO, &data, &registry, false);
+        assert_eq!(meshes.opaque.unwrap().count_vertices(), faces * 4);
+
+        // Every side of a lone chunk of rock becomes a single quad
+        let meshes = generate_mesh(IVec3::ZERO, &data, &registry, true);
+        assert_eq!(meshes.opaque.unwrap().count_vertices(), 6 * 4);
+        assert!(meshes.transparent.is_none());
+    }
+
+    #[test]
+    fn test_transparent_mesh() {
+        let mut registry = Registry::new();
+        registry.register_defaults();
+
+        let glass = Block::new(registry.block_id("glass"), PackedData::builder().build());
+        let mut chunk = ChunkDataInner::new();
+        chunk.set_block(USizeVec3::new(0, 0, 0), Some(glass));
+        chunk.set_block(USizeVec3::new(4, 0, 0), Some(glass));
+        let data = RelevantChunks::new(HashMap::from([(IVec3::ZERO, Arc::new(chunk))]));
+
+        // Glass goes into its own mesh, with both sides of each face
+        let meshes = generate_mesh(IVec3::ZERO, &data, &registry, true);
+        assert!(meshes.opaque.is_none());
+
+        let (mesh, quads) = meshes.transparent.unwrap();
+        assert_eq!(mesh.count_vertices(), 2 * 6 * 4);
+
+        // Quads are sorted furthest first
+        let furthest_quad = |view_pos: Vec3| quads.sorted_indices(view_pos)[0] as usize / 4;
+        assert!(furthest_quad(Vec3::new(-10.0, 0.5, 0.5)) >= 6);
+        assert!(furthest_quad(Vec3::new(10.0, 0.5, 0.5)) < 6);
+    }
+
+    #[test]
+    fn test_tinted_mesh() {
+        let mut registry = Registry::new();
+        registry.register_defaults();
+        registry.register_block(HalfTinted);
+
+        let block = Block::new(
+            registry.block_id("half_tinted"),
+            PackedData::builder().build(),
+        );
+        let data = RelevantChunks::new(HashMap::from([(
+            IVec3::ZERO,
+            Arc::new(ChunkDataInner::filled(Some(block))),
+        )]));
+
+        let tints = [WEST_TINT, EAST_TINT].map(ChunkVertex::pack_tint);
+        for greedy_meshing in [false, true] {
+            let meshes = generate_mesh(IVec3::ZERO, &data, &registry, greedy_meshing);
+            let mesh_tints = mesh_tints(&meshes.opaque.unwrap());
+            assert!(mesh_tints.iter().all(|tint| tints.contains(tint)));
+            assert!(tints.iter().all(|tint| mesh_tints.contains(tint)));
+        }
+
+        // Sides that cross the middle of the chunk split in two where the tint changes
+        let meshes = generate_mesh(IVec3::ZERO, &data, &registry, true);
+        assert_eq!(meshes.opaque.unwrap().count_vertices(), (4 * 2 + 2) * 4);
+    }
 }
@@ -0,0 +1,89 @@
+use std::{fmt, io};
+
+use crate::RegionError;
+
+/// General purpose compression applied to each chunk in a region file, on top of the palette and
+/// run-length encoding done by the chunk itself.
+///
+/// The default is the same in every build, so that worlds saved by one can be read by another.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Codec {
+    None,
+    #[default]
+    Lz4,
+    /// Only available with the `zstd` feature, see `is_supported`.
+    Zstd,
+}
+
+impl Codec {
+    pub const ALL: &[Codec] = &[
+        Codec::None,
+        Codec::Lz4,
+        #[cfg(feature = "zstd")]
+        Codec::Zstd,
+    ];
+
+    /// The codec with the given tag, if this build supports it.
+    pub fn from_tag(tag: u8) -> Option<Self> {
+        let codec = match tag {
+            0 => Self::None,
+            1 => Self::Lz4,
+            2 => Self::Zstd,
+            _ => return None,
+        };
+
+        codec.is_supported().then_some(codec)
+    }
+
+    pub fn tag(self) -> u8 {
+        match self {
+            Self::None => 0,
+            Self::Lz4 => 1,
+            Self::Zstd => 2,
+        }
+    }
+
+    /// Whether this build can read and write the codec.
+    pub fn is_supported(self) -> bool {
+        match self {
+            Self::None | Self::Lz4 => true,
+            Self::Zstd => cfg!(feature = "zstd"),
+        }
+    }
+
+    pub fn compress(self, data: &[u8]) -> Result<Vec<u8>, RegionError> {
+        match self {
+            Self::None => Ok(data.to_vec()),
+            Self::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+            #[cfg(feature = "zstd")]
+            Self::Zstd => Ok(zstd::bulk::compress(data, 3)?),
+            #[cfg(not(feature = "zstd"))]
+            Self::Zstd => Err(RegionError::UnsupportedCodec(self.tag())),
+        }
+    }
+
+    pub fn decompress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Self::None => Ok(data.to_vec()),
+            Self::Lz4 => lz4_flex::decompress_size_prepended(data)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error)),
+            #[cfg(feature = "zstd")]
+            Self::Zstd => zstd::decode_all(data),
+            #[cfg(not(feature = "zstd"))]
+            Self::Zstd => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "zstd isn't enabled in this build",
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => write!(f, "none"),
+            Self::Lz4 => write!(f, "lz4"),
+            Self::Zstd => write!(f, "zstd"),
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{Block, BlockId, MaterialId, Registry};
+
+/// Translates between registry ids, which depend on the order blocks and materials are registered
+/// in, and the ids stored in a world's region files, which are fixed for the lifetime of the world.
+/// The world keeps a table of names for its saved ids, which only ever grows.
+pub struct IdMap {
+    registry: Arc<Registry>,
+    saved_blocks: Vec<Option<BlockId>>,
+    registry_blocks: HashMap<BlockId, BlockId>,
+    saved_materials: Vec<Option<MaterialId>>,
+    registry_materials: HashMap<MaterialId, MaterialId>,
+    is_identity: bool,
+}
+
+impl IdMap {
+    /// Builds the mapping from the world's name tables, adding any blocks and materials that
+    /// the world hasn't seen yet to the end of them.
+    pub fn new(
+        registry: Arc<Registry>,
+        block_names: &mut Vec<String>,
+        material_names: &mut Vec<String>,
+    ) -> Self {
+        for id in registry.blocks() {
+            let name = registry.block_type(id).unique_name();
+
+            if !block_names.contains(&name) {
+                block_names.push(name);
+            }
+        }
+
+        for id in registry.materials() {
+            let name = registry.material(id).unique_name();
+
+            if !material_names.contains(&name) {
+                material_names.push(name);
+            }
+        }
+
+        let saved_blocks = block_names
+            .iter()
+            .map(|name| registry.get_block_id(name))
+            .collect::<Vec<_>>();
+
+        let saved_materials = material_names
+            .iter()
+            .map(|name| registry.get_material_id(name))
+            .collect::<Vec<_>>();
+
+        let missing = block_names
+            .iter()
+            .chain(material_names.iter())
+            .zip(
+                saved_blocks
+                    .iter()
+                    .map(Option::is_none)
+                    .chain(saved_materials.iter().map(Option::is_none)),
+            )
+            .filter_map(|(name, missing)| missing.then_some(name.as_str()))
+            .collect::<Vec<_>>();
+
+        if !missing.is_empty() {
+            log::warn!(
+                "The world contains blocks or materials that are no longer registered, they \
+                 will be loaded as air: {}",
+                missing.join(", ")
+            );
+        }
+
+        let is_identity = saved_blocks
+            .iter()
+            .enumerate()
+            .all(|(index, id)| *id == Some(BlockId(index as u16)))
+            && saved_materials
+                .iter()
+                .enumerate()
+                .all(|(index, id)| *id == Some(MaterialId(index as u16)));
+
+        Self {
+            registry_blocks: invert(&saved_blocks, BlockId),
+            registry_materials: invert(&saved_materials, MaterialId),
+            registry,
+            saved_blocks,
+            saved_materials,
+            is_identity,
+        }
+    }
+
+    /// Whether the saved ids are the same as the registry ids, so nothing needs to be mapped.
+    pub fn is_identity(&self) -> bool {
+        self.is_identity
+    }
+
+    /// Maps a block read from disk to the registry, returning `None` if it's no longer registered.
+    pub fn load_block(&self, block: Block) -> Option<Block> {
+        let id = (*self.saved_blocks.get(block.id.0 as usize)?)?;
+
+        let data = self
+            .registry
+            .block_type(id)
+            .map_materials(block.data, &|material| {
+                self.saved_materials
+                    .get(material.0 as usize)
+                    .copied()
+                    .flatten()
+            })?;
+
+        Some(Block::new(id, data))
+    }
+
+    /// Maps a block from the registry to the ids that are written to disk.
+    pub fn save_block(&self, block: Block) -> Block {
+        let id = self.registry_blocks[&block.id];
+
+        // Every registered material has a saved id, so this can't fail for valid block data
+        let data = self
+            .registry
+            .block_type(block.id)
+            .map_materials(block.data, &|material| {
+                self.registry_materials.get(&material).copied()
+            })
+            .unwrap_or(block.data);
+
+        Block::new(id, data)
+    }
+}
+
+fn invert<T: Copy + Eq + std::hash::Hash>(
+    saved: &[Option<T>],
+    saved_id: impl Fn(u16) -> T,
+) -> HashMap<T, T> {
+    saved
+        .iter()
+        .enumerate()
+        .filter_map(|(index, id)| Some(((*id)?, saved_id(index as u16))))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::PackedData;
+
+    #[test]
+    fn test_id_map() {
+        let mut registry = Registry::new();
+        registry.register_defaults();
+        let registry = Arc::new(registry);
+
+        // A world saved before rock existed, with a block that has since been removed
+        let mut block_names = vec!["glass".to_string(), "marble".to_string()];
+        let mut material_names = vec!["shale".to_string()];
+        let ids = IdMap::new(registry.clone(), &mut block_names, &mut material_names);
+
+        assert!(!ids.is_identity());
+        assert_eq!(block_names.len(), 6);
+        assert_eq!(material_names.len(), 4);
+
+        let rock = registry.block_id("rock");
+        let shale = registry.material_id("shale");
+        let block = Block::new(rock, PackedData::builder().with_material(shale).build());
+
+        // Blocks and the materials inside of their data round trip through the saved ids
+        let saved = ids.save_block(block);
+        assert_eq!(saved.id, BlockId(2));
+        assert_eq!(saved.data.decode().take_material(), MaterialId(0));
+        assert_eq!(ids.load_block(saved), Some(block));
+
+        // Blocks that are no longer registered are dropped
+        assert_eq!(
+            ids.load_block(Block::new(BlockId(1), PackedData::builder().build())),
+            None
+        );
+
+        // A world created with the current registry doesn't need any mapping
+        let ids = IdMap::new(registry, &mut Vec::new(), &mut Vec::new());
+        assert!(ids.is_identity());
+    }
+
+    /// Block data using each material in turn, in the layout each block type stores. Every
+    /// registered block needs an entry, so new blocks can't skip the round trip below.
+    fn sample_data(registry: &Registry, name: &str) -> Vec<PackedData> {
+        let materials = registry.materials();
+
+        match name {
+            "glass" => vec![PackedData::builder().build()],
+            "rock" | "rock_slab" | "wood" => materials
+                .iter()
+                .map(|&material| PackedData::builder().with_material(material).build())
+                .collect(),
+            "soil" => materials
+                .iter()
+                .flat_map(|&soil| {
+                    let bare = PackedData::builder()
+                        .with_material(soil)
+                        .with_bool(false)
+                        .build();
+                    let grassy = materials.iter().map(move |&grass| {
+                        PackedData::builder()
+                            .with_material(soil)
+                            .with_bool(true)
+                            .with_material(grass)
+                            .build()
+                    });
+
+                    std::iter::once(bare).chain(grassy)
+                })
+                .collect(),
+            _ => panic!("no sample data for block {name}"),
+        }
+    }
+
+    #[test]
+    fn test_map_materials() {
+        let mut registry = Registry::new();
+        registry.register_defaults();
+        let registry = Arc::new(registry);
+
+        // A world that registered everything in the opposite order, so every saved id differs
+        let mut block_names = registry
+            .blocks()
+            .into_iter()
+            .rev()
+            .map(|id| registry.block_type(id).unique_name())
+            .collect();
+        let mut materials = registry.materials();
+        materials.sort_by_key(|id| std::cmp::Reverse(id.0));
+        let mut material_names = materials
+            .into_iter()
+            .map(|id| registry.material(id).unique_name())
+            .collect();
+
+        let ids = IdMap::new(registry.clone(), &mut block_names, &mut material_names);
+        assert!(!ids.is_identity());
+
+        for id in registry.blocks() {
+            let name = registry.block_type(id).unique_name();
+
+            for data in sample_data(&registry, &name) {
+                let block = Block::new(id, data);
+                let saved = ids.save_block(block);
+                assert_eq!(ids.load_block(saved), Some(block));
+
+                // Blocks only store materials, which all have different saved ids
+                if data != PackedData::builder().build() {
+                    assert_ne!(saved.data, data, "the materials of {name} weren't mapped");
+                }
+            }
+        }
+    }
+}
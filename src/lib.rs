@@ -3,6 +3,9 @@ mod blocks;
 mod chunk_data;
 mod chunk_material;
 mod chunk_mesh;
+mod compression;
+mod id_map;
+mod light;
 mod material;
 mod materials;
 mod model;
@@ -12,13 +15,19 @@ mod physics;
 mod plugins;
 mod region;
 mod relevant_chunks;
+mod task_budget;
+mod view_distance;
 mod world_generator;
+mod world_metadata;
 
 pub use block::*;
 pub use blocks::*;
 pub use chunk_data::*;
 pub use chunk_material::*;
 pub use chunk_mesh::*;
+pub use compression::*;
+pub use id_map::*;
+pub use light::*;
 pub use material::*;
 pub use materials::*;
 pub use model::*;
@@ -28,4 +37,7 @@ pub use physics::*;
 pub use plugins::*;
 pub use region::*;
 pub use relevant_chunks::*;
+pub use task_budget::*;
+pub use view_distance::*;
 pub use world_generator::*;
+pub use world_metadata::*;
@@ -0,0 +1,827 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::Arc,
+    time::Instant,
+};
+
+use bevy::{math::USizeVec3, prelude::*};
+
+use crate::{Block, CHUNK_SIZE, ChunkData, Registry, World, border_neighbors};
+
+const CHUNK_VOLUME: usize = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
+
+const DIRECTIONS: [IVec3; 6] = [
+    IVec3::X,
+    IVec3::NEG_X,
+    IVec3::Y,
+    IVec3::NEG_Y,
+    IVec3::Z,
+    IVec3::NEG_Z,
+];
+
+/// The sky and block light at a voxel, from 0 to `Light::MAX` each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Light(u8);
+
+impl Light {
+    pub const MAX: u8 = 15;
+
+    pub fn new(sky: u8, block: u8) -> Self {
+        Self((sky.min(Self::MAX) << 4) | block.min(Self::MAX))
+    }
+
+    /// Light coming from the open sky.
+    pub fn sky(self) -> u8 {
+        self.0 >> 4
+    }
+
+    /// Light coming from light emitting blocks.
+    pub fn block(self) -> u8 {
+        self.0 & 0xF
+    }
+
+    /// Both levels packed into a byte, with the sky light in the high bits.
+    pub fn packed(self) -> u8 {
+        self.0
+    }
+
+    fn get(self, channel: LightChannel) -> u8 {
+        match channel {
+            LightChannel::Sky => self.sky(),
+            LightChannel::Block => self.block(),
+        }
+    }
+
+    fn with(self, channel: LightChannel, level: u8) -> Self {
+        match channel {
+            LightChannel::Sky => Self::new(level, self.block()),
+            LightChannel::Block => Self::new(self.sky(), level),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LightChannel {
+    Sky,
+    Block,
+}
+
+/// The light of every voxel in a chunk, stored alongside its `ChunkData`. Like the block data it's
+/// shared with mesh tasks, and only copied when it changes while they hold it.
+#[derive(Debug, Clone)]
+pub enum ChunkLight {
+    Uniform(Light),
+    Mixed(Arc<Vec<Light>>),
+}
+
+impl Default for ChunkLight {
+    fn default() -> Self {
+        Self::Uniform(Light::default())
+    }
+}
+
+impl ChunkLight {
+    pub fn get(&self, local_pos: USizeVec3) -> Light {
+        match self {
+            Self::Uniform(light) => *light,
+            Self::Mixed(lights) => lights[light_index(local_pos)],
+        }
+    }
+
+    pub fn set(&mut self, local_pos: USizeVec3, light: Light) {
+        match self {
+            Self::Uniform(uniform) if *uniform == light => {}
+            Self::Uniform(uniform) => {
+                let mut lights = vec![*uniform; CHUNK_VOLUME];
+                lights[light_index(local_pos)] = light;
+                *self = Self::Mixed(Arc::new(lights));
+            }
+            Self::Mixed(lights) => Arc::make_mut(lights)[light_index(local_pos)] = light,
+        }
+    }
+
+    /// Shrinks the light to match `ChunkDataInner::downsample`, keeping the brightest voxel of
+    /// each cell so faces next to downsampled blocks aren't darkened by the blocks themselves.
+    pub fn downsample(&self, scale: usize) -> Self {
+        let Self::Mixed(lights) = self else {
+            return self.clone();
+        };
+
+        let size = CHUNK_SIZE / scale;
+        let mut downsampled = vec![Light::default(); CHUNK_VOLUME];
+
+        for x in 0..size {
+            for y in 0..size {
+                for z in 0..size {
+                    let (mut sky, mut block) = (0, 0);
+
+                    for offset_x in 0..scale {
+                        for offset_y in 0..scale {
+                            for offset_z in 0..scale {
+                                let light = lights[light_index(USizeVec3::new(
+                                    x * scale + offset_x,
+                                    y * scale + offset_y,
+                                    z * scale + offset_z,
+                                ))];
+
+                                sky = light.sky().max(sky);
+                                block = light.block().max(block);
+                            }
+                        }
+                    }
+
+                    downsampled[light_index(USizeVec3::new(x, y, z))] = Light::new(sky, block);
+                }
+            }
+        }
+
+        Self::Mixed(Arc::new(downsampled))
+    }
+}
+
+fn light_index(local_pos: USizeVec3) -> usize {
+    local_pos.x + local_pos.y * CHUNK_SIZE + local_pos.z * CHUNK_SIZE * CHUNK_SIZE
+}
+
+/// The chunks that light can spread through.
+pub trait LightChunks {
+    /// The blocks and light of a chunk, or `None` if light can't reach it yet.
+    fn get_chunk(&self, chunk_pos: IVec3) -> Option<(&ChunkData, &ChunkLight)>;
+
+    fn get_light_mut(&mut self, chunk_pos: IVec3) -> Option<&mut ChunkLight>;
+
+    /// Whether sky light shines in from a chunk that isn't lit, because nothing will be loaded
+    /// there to block it. Otherwise light is left to spread in once the chunk has been lit.
+    fn is_open_sky(&self, chunk_pos: IVec3) -> bool {
+        self.get_chunk(chunk_pos).is_none()
+    }
+
+    /// How far from the border of a chunk its light can still show up in a neighbor's mesh, see
+    /// `border_neighbors`.
+    fn border_scale(&self, _chunk_pos: IVec3) -> usize {
+        1
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BlockLight {
+    is_opaque: bool,
+    emission: u8,
+}
+
+/// Light that's still spreading. It's kept between frames, so propagation can stop whenever it
+/// runs out of time and carry on where it left off.
+#[derive(Debug, Default)]
+pub struct LightQueue {
+    sky: ChannelQueue,
+    block: ChannelQueue,
+}
+
+#[derive(Debug, Default)]
+struct ChannelQueue {
+    /// Voxels that were darkened, with the level they had, whose neighbors may depend on them.
+    removals: VecDeque<(IVec3, u8)>,
+    /// Voxels whose light spreads to their neighbors.
+    additions: VecDeque<IVec3>,
+}
+
+impl LightQueue {
+    pub fn is_empty(&self) -> bool {
+        [&self.sky, &self.block]
+            .iter()
+            .all(|queue| queue.removals.is_empty() && queue.additions.is_empty())
+    }
+
+    fn channel(&mut self, channel: LightChannel) -> &mut ChannelQueue {
+        match channel {
+            LightChannel::Sky => &mut self.sky,
+            LightChannel::Block => &mut self.block,
+        }
+    }
+
+    fn pop_removal(&mut self) -> Option<(LightChannel, IVec3, u8)> {
+        [LightChannel::Sky, LightChannel::Block]
+            .into_iter()
+            .find_map(|channel| {
+                let (pos, level) = self.channel(channel).removals.pop_front()?;
+                Some((channel, pos, level))
+            })
+    }
+
+    fn pop_addition(&mut self) -> Option<(LightChannel, IVec3)> {
+        [LightChannel::Sky, LightChannel::Block]
+            .into_iter()
+            .find_map(|channel| Some((channel, self.channel(channel).additions.pop_front()?)))
+    }
+
+    /// The chunks that the queued light may still change.
+    pub fn pending(&self) -> PendingLight {
+        let mut top_chunks = HashMap::new();
+
+        for queue in [&self.sky, &self.block] {
+            let removals = queue.removals.iter().map(|&(pos, _)| pos);
+
+            for pos in removals.chain(queue.additions.iter().copied()) {
+                let chunk_pos = World::chunk_pos(pos);
+                let top = top_chunks.entry(chunk_pos.xz()).or_insert(chunk_pos.y);
+                *top = chunk_pos.y.max(*top);
+            }
+        }
+
+        PendingLight { top_chunks }
+    }
+}
+
+/// Where light is still queued, as the highest chunk with queued light in each column of chunks.
+///
+/// Light fades out before it crosses a whole chunk, except for full sky light which carries on
+/// straight down. So queued light can only change the chunks below it and the ones it spreads
+/// into next to them, along with the chunks whose meshes sample their borders.
+pub struct PendingLight {
+    top_chunks: HashMap<IVec2, i32>,
+}
+
+impl PendingLight {
+    pub fn may_change(&self, chunk_pos: IVec3) -> bool {
+        (-2..=2).any(|x| {
+            (-2..=2).any(|z| {
+                self.top_chunks
+                    .get(&(chunk_pos.xz() + IVec2::new(x, z)))
+                    .is_some_and(|&top| top >= chunk_pos.y - 2)
+            })
+        })
+    }
+}
+
+/// Checking the time is slow next to a single step of propagation, so it's only done this often.
+const STEPS_PER_DEADLINE_CHECK: usize = 256;
+
+/// Flood fills sky and block light through loaded chunks.
+///
+/// Sky light enters through the top of chunks with nothing loaded above them and travels straight
+/// down at full strength, while everything else loses one level per block. Changes are applied
+/// incrementally, by first removing the light that depended on what changed and then spreading
+/// light back in from whatever is still lit around it.
+pub struct LightEngine<'a, C: LightChunks> {
+    chunks: &'a mut C,
+    registry: &'a Registry,
+    queue: LightQueue,
+    block_lights: HashMap<Block, BlockLight>,
+    border_scales: HashMap<IVec3, usize>,
+    changed_chunks: HashSet<IVec3>,
+}
+
+impl<'a, C: LightChunks> LightEngine<'a, C> {
+    pub fn new(chunks: &'a mut C, registry: &'a Registry) -> Self {
+        Self::resume(chunks, registry, LightQueue::default())
+    }
+
+    /// Continues with light that an earlier engine didn't finish spreading.
+    pub fn resume(chunks: &'a mut C, registry: &'a Registry, queue: LightQueue) -> Self {
+        Self {
+            chunks,
+            registry,
+            queue,
+            block_lights: HashMap::new(),
+            border_scales: HashMap::new(),
+            changed_chunks: HashSet::new(),
+        }
+    }
+
+    /// The light that's still queued, and the chunks whose meshes show light that changed,
+    /// including neighbors that sample it across their borders.
+    pub fn into_parts(self) -> (LightQueue, HashSet<IVec3>) {
+        (self.queue, self.changed_chunks)
+    }
+
+    /// Queues light for a chunk that was just made available, starting from darkness. Light flows
+    /// in from its own sources and lit neighbors, and sky light that was assumed to reach the
+    /// chunk below is taken away if this chunk blocks it.
+    pub fn light_chunk(&mut self, chunk_pos: IVec3) {
+        let Some((data, _)) = self.chunks.get_chunk(chunk_pos) else {
+            return;
+        };
+
+        let data = data.clone();
+        let origin = chunk_pos * CHUNK_SIZE as i32;
+        let first_block = data.get_block(USizeVec3::ZERO);
+
+        // Air or see-through blocks under open sky are lit by it all the way through, so there's
+        // nothing to flood
+        let above = chunk_pos + IVec3::Y;
+
+        if data.is_uniform()
+            && first_block.is_none_or(|block| {
+                let block_light = self.block_light(block);
+                !block_light.is_opaque && block_light.emission == 0
+            })
+            && self.chunks.get_chunk(above).is_none()
+            && self.chunks.is_open_sky(above)
+        {
+            self.light_open_sky_chunk(chunk_pos);
+            return;
+        }
+
+        // Sky light from above, when there's no chunk there to pass it down
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let pos = origin + IVec3::new(x as i32, CHUNK_SIZE as i32 - 1, z as i32);
+                self.add_source(pos, LightChannel::Sky);
+            }
+        }
+
+        // Light emitting blocks, which uniform chunks either have everywhere or nowhere
+        if !data.is_uniform()
+            || first_block.is_some_and(|block| self.block_light(block).emission > 0)
+        {
+            for (index, block) in data.iter().enumerate() {
+                if block.is_some_and(|block| self.block_light(block).emission > 0) {
+                    let local_pos = IVec3::new(
+                        (index % CHUNK_SIZE) as i32,
+                        (index / CHUNK_SIZE % CHUNK_SIZE) as i32,
+                        (index / (CHUNK_SIZE * CHUNK_SIZE)) as i32,
+                    );
+                    self.add_source(origin + local_pos, LightChannel::Block);
+                }
+            }
+        }
+
+        // Light already in the neighbors, which spreads in across the borders
+        for direction in DIRECTIONS {
+            if self.chunks.get_chunk(chunk_pos + direction).is_none() {
+                continue;
+            }
+
+            for (_, neighbor_pos) in border_layer(chunk_pos, direction) {
+                let Some(light) = self.light(neighbor_pos) else {
+                    continue;
+                };
+
+                if light.sky() > 0 {
+                    self.queue.sky.additions.push_back(neighbor_pos);
+                }
+
+                if light.block() > 0 {
+                    self.queue.block.additions.push_back(neighbor_pos);
+                }
+            }
+        }
+
+        // The chunk below may have assumed open sky above it, which this chunk now blocks. Full
+        // sky light only travels straight down, so that's known before any light has spread
+        for (pos, below_pos) in border_layer(chunk_pos, IVec3::NEG_Y) {
+            let Some(below) = self.light(below_pos) else {
+                continue;
+            };
+
+            if below.sky() == Light::MAX && !self.is_sky_column(pos) {
+                self.set_light(below_pos, below.with(LightChannel::Sky, 0));
+                self.queue.sky.removals.push_back((below_pos, Light::MAX));
+            }
+        }
+    }
+
+    /// Gives a chunk full sky light everywhere, then queues the light crossing its borders, which
+    /// is all that's left to spread.
+    fn light_open_sky_chunk(&mut self, chunk_pos: IVec3) {
+        let Some(light) = self.chunks.get_light_mut(chunk_pos) else {
+            return;
+        };
+
+        *light = ChunkLight::Uniform(Light::new(Light::MAX, 0));
+
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    self.changed_chunks.insert(chunk_pos + IVec3::new(x, y, z));
+                }
+            }
+        }
+
+        for direction in DIRECTIONS {
+            if self.chunks.get_chunk(chunk_pos + direction).is_none() {
+                continue;
+            }
+
+            // Sky light spreads out into lit neighbors, and their block light spreads in
+            for (pos, neighbor_pos) in border_layer(chunk_pos, direction) {
+                self.queue.sky.additions.push_back(pos);
+
+                if self
+                    .light(neighbor_pos)
+                    .is_some_and(|light| light.block() > 0)
+                {
+                    self.queue.block.additions.push_back(neighbor_pos);
+                }
+            }
+        }
+    }
+
+    /// Queues relighting around blocks that were changed in the chunk data.
+    pub fn update_blocks(&mut self, positions: &[IVec3]) {
+        for channel in [LightChannel::Sky, LightChannel::Block] {
+            for &pos in positions {
+                let Some(light) = self.light(pos) else {
+                    continue;
+                };
+
+                let old_level = light.get(channel);
+                self.set_light(pos, light.with(channel, 0));
+
+                if old_level > 0 {
+                    self.queue
+                        .channel(channel)
+                        .removals
+                        .push_back((pos, old_level));
+                }
+
+                self.add_source(pos, channel);
+
+                // Light around the block flows back in if it no longer blocks it
+                for direction in DIRECTIONS {
+                    self.queue
+                        .channel(channel)
+                        .additions
+                        .push_back(pos + direction);
+                }
+            }
+        }
+    }
+
+    /// Spreads the queued light until it settles, or until `deadline` passes with the rest left
+    /// queued. Returns whether it settled.
+    pub fn propagate(&mut self, deadline: Option<Instant>) -> bool {
+        for step in 1.. {
+            if step % STEPS_PER_DEADLINE_CHECK == 0
+                && deadline.is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                break;
+            }
+
+            // Removals go first, so light isn't spread from voxels that are about to be darkened
+            if let Some((channel, pos, level)) = self.queue.pop_removal() {
+                self.spread_removal(channel, pos, level);
+            } else if let Some((channel, pos)) = self.queue.pop_addition() {
+                self.spread_addition(channel, pos);
+            } else {
+                break;
+            }
+        }
+
+        self.queue.is_empty()
+    }
+
+    /// Darkens the neighbors that were lit by removed light, and queues the light at the edge of
+    /// the darkened area to spread back in.
+    fn spread_removal(&mut self, channel: LightChannel, pos: IVec3, level: u8) {
+        for direction in DIRECTIONS {
+            let neighbor_pos = pos + direction;
+
+            let Some(neighbor) = self.light(neighbor_pos) else {
+                continue;
+            };
+
+            let neighbor_level = neighbor.get(channel);
+
+            if neighbor_level == 0 {
+                continue;
+            }
+
+            let is_sky_column =
+                channel == LightChannel::Sky && direction == IVec3::NEG_Y && level == Light::MAX;
+
+            if neighbor_level < level || is_sky_column {
+                self.set_light(neighbor_pos, neighbor.with(channel, 0));
+                self.queue
+                    .channel(channel)
+                    .removals
+                    .push_back((neighbor_pos, neighbor_level));
+                self.add_source(neighbor_pos, channel);
+            } else {
+                self.queue
+                    .channel(channel)
+                    .additions
+                    .push_back(neighbor_pos);
+            }
+        }
+    }
+
+    fn spread_addition(&mut self, channel: LightChannel, pos: IVec3) {
+        let Some(light) = self.light(pos) else {
+            return;
+        };
+
+        let level = light.get(channel);
+
+        if level <= 1 {
+            return;
+        }
+
+        for direction in DIRECTIONS {
+            let neighbor_pos = pos + direction;
+
+            let Some(block_light) = self.block_light_at(neighbor_pos) else {
+                continue;
+            };
+
+            if block_light.is_opaque {
+                continue;
+            }
+
+            let neighbor_level =
+                if channel == LightChannel::Sky && direction == IVec3::NEG_Y && level == Light::MAX
+                {
+                    Light::MAX
+                } else {
+                    level - 1
+                };
+
+            let Some(neighbor) = self.light(neighbor_pos) else {
+                continue;
+            };
+
+            if neighbor.get(channel) < neighbor_level {
+                self.set_light(neighbor_pos, neighbor.with(channel, neighbor_level));
+                self.queue
+                    .channel(channel)
+                    .additions
+                    .push_back(neighbor_pos);
+            }
+        }
+    }
+
+    /// Lights a voxel from its own source, if it has one, and queues it to spread.
+    fn add_source(&mut self, pos: IVec3, channel: LightChannel) {
+        let Some(block_light) = self.block_light_at(pos) else {
+            return;
+        };
+
+        let level = match channel {
+            LightChannel::Block => block_light.emission,
+            // Nothing above the top of the loaded chunks is assumed to block the sky
+            LightChannel::Sky => {
+                let above = pos + IVec3::Y;
+
+                if !block_light.is_opaque
+                    && self.light(above).is_none()
+                    && self.chunks.is_open_sky(World::chunk_pos(above))
+                {
+                    Light::MAX
+                } else {
+                    0
+                }
+            }
+        };
+
+        let Some(light) = self.light(pos) else {
+            return;
+        };
+
+        if level > light.get(channel) {
+            self.set_light(pos, light.with(channel, level));
+            self.queue.channel(channel).additions.push_back(pos);
+        }
+    }
+
+    /// Whether full sky light reaches `pos` once it has spread, which it only does straight down
+    /// from the sky through blocks that let light through.
+    fn is_sky_column(&mut self, pos: IVec3) -> bool {
+        let top = (World::chunk_pos(pos).y + 1) * CHUNK_SIZE as i32;
+
+        for y in pos.y..top {
+            let block_light = self.block_light_at(IVec3::new(pos.x, y, pos.z));
+
+            if block_light.is_none_or(|block_light| block_light.is_opaque) {
+                return false;
+            }
+        }
+
+        let above = IVec3::new(pos.x, top, pos.z);
+
+        match self.light(above) {
+            Some(light) => light.sky() == Light::MAX,
+            None => self.chunks.is_open_sky(World::chunk_pos(above)),
+        }
+    }
+
+    fn light(&self, pos: IVec3) -> Option<Light> {
+        let (_, light) = self.chunks.get_chunk(World::chunk_pos(pos))?;
+        Some(light.get(World::local_pos(pos)))
+    }
+
+    fn set_light(&mut self, pos: IVec3, light: Light) {
+        let chunk_pos = World::chunk_pos(pos);
+        let local_pos = World::local_pos(pos);
+
+        let Some(chunk_light) = self.chunks.get_light_mut(chunk_pos) else {
+            return;
+        };
+
+        chunk_light.set(local_pos, light);
+
+        let scale = *self
+            .border_scales
+            .entry(chunk_pos)
+            .or_insert_with(|| self.chunks.border_scale(chunk_pos));
+
+        if local_pos.cmplt(USizeVec3::splat(scale)).any()
+            || local_pos.cmpge(USizeVec3::splat(CHUNK_SIZE - scale)).any()
+        {
+            self.changed_chunks.extend(
+                border_neighbors(local_pos, scale)
+                    .into_iter()
+                    .map(|offset| chunk_pos + offset),
+            );
+        } else {
+            self.changed_chunks.insert(chunk_pos);
+        }
+    }
+
+    fn block_light_at(&mut self, pos: IVec3) -> Option<BlockLight> {
+        let (data, _) = self.chunks.get_chunk(World::chunk_pos(pos))?;
+        let block = data.get_block(World::local_pos(pos));
+
+        Some(match block {
+            Some(block) => self.block_light(block),
+            None => BlockLight {
+                is_opaque: false,
+                emission: 0,
+            },
+        })
+    }
+
+    fn block_light(&mut self, block: Block) -> BlockLight {
+        *self.block_lights.entry(block).or_insert_with(|| {
+            let block_type = self.registry.block_type(block.id);
+
+            BlockLight {
+                is_opaque: block_type.is_opaque_cube(block.data),
+                emission: block_type.light_emission(block.data).min(Light::MAX),
+            }
+        })
+    }
+}
+
+/// Pairs each voxel on one side of a chunk with the voxel next to it in the neighboring chunk.
+fn border_layer(chunk_pos: IVec3, direction: IVec3) -> Vec<(IVec3, IVec3)> {
+    let origin = chunk_pos * CHUNK_SIZE as i32;
+    let last = CHUNK_SIZE as i32 - 1;
+    let mut pairs = Vec::with_capacity(CHUNK_SIZE * CHUNK_SIZE);
+
+    for a in 0..CHUNK_SIZE as i32 {
+        for b in 0..CHUNK_SIZE as i32 {
+            let local_pos = match direction {
+                IVec3::X => IVec3::new(last, a, b),
+                IVec3::NEG_X => IVec3::new(0, a, b),
+                IVec3::Y => IVec3::new(a, last, b),
+                IVec3::NEG_Y => IVec3::new(a, 0, b),
+                IVec3::Z => IVec3::new(a, b, last),
+                _ => IVec3::new(a, b, 0),
+            };
+
+            let pos = origin + local_pos;
+            pairs.push((pos, pos + direction));
+        }
+    }
+
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BlockType, ChunkDataInner, MaterialId, PackedData};
+
+    use super::*;
+
+    struct Lamp;
+
+    impl BlockType for Lamp {
+        fn unique_name(&self) -> String {
+            "lamp".to_string()
+        }
+
+        fn register(&self, _registry: &mut Registry) {}
+
+        fn light_emission(&self, _data: PackedData) -> u8 {
+            Light::MAX
+        }
+
+        fn map_materials(
+            &self,
+            data: PackedData,
+            _map: &dyn Fn(MaterialId) -> Option<MaterialId>,
+        ) -> Option<PackedData> {
+            Some(data)
+        }
+    }
+
+    impl LightChunks for HashMap<IVec3, (ChunkData, ChunkLight)> {
+        fn get_chunk(&self, chunk_pos: IVec3) -> Option<(&ChunkData, &ChunkLight)> {
+            self.get(&chunk_pos).map(|(data, light)| (data, light))
+        }
+
+        fn get_light_mut(&mut self, chunk_pos: IVec3) -> Option<&mut ChunkLight> {
+            self.get_mut(&chunk_pos).map(|(_, light)| light)
+        }
+    }
+
+    fn set_block(
+        chunks: &mut HashMap<IVec3, (ChunkData, ChunkLight)>,
+        registry: &Registry,
+        pos: IVec3,
+        block: Option<Block>,
+    ) {
+        let (data, _) = chunks.get_mut(&World::chunk_pos(pos)).unwrap();
+        Arc::make_mut(data).set_block(World::local_pos(pos), block);
+        let mut engine = LightEngine::new(chunks, registry);
+        engine.update_blocks(&[pos]);
+        assert!(engine.propagate(None));
+    }
+
+    fn light(chunks: &HashMap<IVec3, (ChunkData, ChunkLight)>, pos: IVec3) -> Light {
+        chunks[&World::chunk_pos(pos)].1.get(World::local_pos(pos))
+    }
+
+    #[test]
+    fn test_light() {
+        let mut registry = Registry::new();
+        registry.register_defaults();
+        registry.register_block(Lamp);
+
+        let shale = registry.material_id("shale");
+        let rock = Block::new(
+            registry.block_id("rock"),
+            PackedData::builder().with_material(shale).build(),
+        );
+        let lamp = Block::new(registry.block_id("lamp"), PackedData::builder().build());
+
+        // A chunk with a roof of rock across it, which nothing above blocks
+        let mut data = ChunkDataInner::filled(None);
+
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                data.set_block(USizeVec3::new(x, 20, z), Some(rock));
+            }
+        }
+
+        let mut chunks = HashMap::from([(IVec3::ZERO, (Arc::new(data), ChunkLight::default()))]);
+        let mut engine = LightEngine::new(&mut chunks, &registry);
+        engine.light_chunk(IVec3::ZERO);
+
+        // Propagation stops once it's out of time, and a later engine carries on with the rest
+        assert!(!engine.propagate(Some(Instant::now())));
+        let (queue, _) = engine.into_parts();
+        assert!(!queue.is_empty());
+
+        let mut engine = LightEngine::resume(&mut chunks, &registry, queue);
+        assert!(engine.propagate(None));
+
+        let (queue, changed_chunks) = engine.into_parts();
+        assert!(queue.is_empty());
+        assert!(changed_chunks.contains(&IVec3::ZERO));
+
+        assert_eq!(light(&chunks, IVec3::new(5, 25, 5)), Light::new(15, 0));
+        assert_eq!(light(&chunks, IVec3::new(5, 10, 5)), Light::new(0, 0));
+
+        // Block light fades by one level per block
+        set_block(&mut chunks, &registry, IVec3::new(5, 10, 5), Some(lamp));
+        assert_eq!(light(&chunks, IVec3::new(5, 10, 5)).block(), 15);
+        assert_eq!(light(&chunks, IVec3::new(6, 10, 5)).block(), 14);
+        assert_eq!(light(&chunks, IVec3::new(7, 11, 5)).block(), 12);
+
+        set_block(&mut chunks, &registry, IVec3::new(5, 10, 5), None);
+        assert_eq!(light(&chunks, IVec3::new(5, 10, 5)).block(), 0);
+        assert_eq!(light(&chunks, IVec3::new(7, 11, 5)).block(), 0);
+
+        // Sky light falls straight through a hole in the roof, and spreads out below it
+        set_block(&mut chunks, &registry, IVec3::new(16, 20, 16), None);
+        assert_eq!(light(&chunks, IVec3::new(16, 0, 16)).sky(), 15);
+        assert_eq!(light(&chunks, IVec3::new(18, 10, 16)).sky(), 13);
+
+        set_block(&mut chunks, &registry, IVec3::new(16, 20, 16), Some(rock));
+        assert_eq!(light(&chunks, IVec3::new(16, 10, 16)), Light::new(0, 0));
+
+        // A chunk of air under open sky is lit all the way through without flooding it
+        let air = Arc::new(ChunkDataInner::filled(None));
+        chunks.insert(IVec3::Y, (air, ChunkLight::default()));
+        let mut engine = LightEngine::new(&mut chunks, &registry);
+        engine.light_chunk(IVec3::Y);
+        assert!(engine.propagate(None));
+
+        assert!(matches!(
+            chunks[&IVec3::Y].1,
+            ChunkLight::Uniform(light) if light == Light::new(15, 0)
+        ));
+        assert_eq!(light(&chunks, IVec3::new(5, 25, 5)), Light::new(15, 0));
+        assert_eq!(light(&chunks, IVec3::new(5, 10, 5)), Light::new(0, 0));
+
+        // Queued light only holds back the chunks it can still reach
+        let mut queue = LightQueue::default();
+        queue.sky.additions.push_back(IVec3::new(5, 25, 5));
+        let pending = queue.pending();
+        assert!(pending.may_change(IVec3::new(0, -4, 0)));
+        assert!(pending.may_change(IVec3::new(2, 2, 0)));
+        assert!(!pending.may_change(IVec3::new(0, 3, 0)));
+        assert!(!pending.may_change(IVec3::new(3, 0, 0)));
+    }
+}
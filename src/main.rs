@@ -21,7 +21,7 @@ fn main() -> Result<()> {
             TransformInterpolationPlugin::default(),
             RegistryPlugin,
             PlayerPlugin,
-            WorldPlugin,
+            WorldPlugin::default(),
             PhysicsPlugin,
             HudPlugin,
         ))
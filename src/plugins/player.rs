@@ -5,7 +5,9 @@ use bevy::{
 };
 use bevy_transform_interpolation::prelude::TransformInterpolation;
 
-use crate::{Aabb, Block, CollisionNormals, PackedData, SharedRegistry, Velocity, World};
+use crate::{
+    Aabb, Block, BlockChangeCause, CollisionNormals, PackedData, SharedRegistry, Velocity, World,
+};
 
 pub struct PlayerPlugin;
 
@@ -231,7 +233,7 @@ fn update_player(
             && let Some(result) =
                 voxel_raycast(camera_global.translation(), forward_with_pitch, 5.0, &world)
         {
-            world.set_block(result.hit_position, None);
+            world.set_block(result.hit_position, None, BlockChangeCause::Player);
         } else if mouse_input.just_pressed(MouseButton::Right)
             && let Some(result) =
                 voxel_raycast(camera_global.translation(), forward_with_pitch, 5.0, &world)
@@ -255,7 +257,11 @@ fn update_player(
                 }
             };
 
-            world.set_block(result.previous_position, Some(block));
+            world.set_block(
+                result.previous_position,
+                Some(block),
+                BlockChangeCause::Player,
+            );
         }
     }
 
@@ -30,6 +30,8 @@ impl Plugin for RegistryPlugin {
 pub struct BlockTextureArray {
     pub handle: Handle<Image>,
     pub material: Handle<ChunkMaterial>,
+    /// The material for transparent faces, which are blended rather than cut out.
+    pub transparent_material: Handle<ChunkMaterial>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -73,6 +75,10 @@ impl Registry {
         &*self.materials[&id]
     }
 
+    pub fn get_material_id(&self, name: &str) -> Option<MaterialId> {
+        self.material_ids.get(name).copied()
+    }
+
     pub fn materials(&self) -> Vec<MaterialId> {
         self.material_ids.values().copied().collect()
     }
@@ -104,10 +110,18 @@ impl Registry {
         self.block_ids[name]
     }
 
+    pub fn get_block_id(&self, name: &str) -> Option<BlockId> {
+        self.block_ids.get(name).copied()
+    }
+
     pub fn block_type(&self, id: BlockId) -> &dyn BlockType {
         &*self.block_types[&id]
     }
 
+    pub fn blocks(&self) -> Vec<BlockId> {
+        (0..self.block_ids.len() as u16).map(BlockId).collect()
+    }
+
     pub fn model_id(&self, name: &str) -> ModelId {
         self.model_ids[name]
     }
@@ -137,6 +151,27 @@ impl Registry {
     pub fn model_offset(&self, model_id: ModelId) -> u32 {
         self.model_offsets[&model_id]
     }
+
+    /// A vertex of a registered model, by its index in the model buffer.
+    pub fn model_vertex(&self, index: u32) -> ModelVertex {
+        self.model_data[index as usize]
+    }
+
+    pub fn register_defaults(&mut self) {
+        self.register_model(Cube);
+        self.register_model(Slab);
+
+        self.register_material(Loam);
+        self.register_material(LushGrass);
+        self.register_material(Oak);
+        self.register_material(Shale);
+
+        self.register_block(Rock);
+        self.register_block(RockSlab);
+        self.register_block(Soil);
+        self.register_block(Wood);
+        self.register_block(Glass);
+    }
 }
 
 fn setup_registry(
@@ -146,20 +181,7 @@ fn setup_registry(
     mut buffers: ResMut<Assets<ShaderStorageBuffer>>,
 ) {
     let mut registry = Registry::new();
-
-    registry.register_model(Cube);
-    registry.register_model(Slab);
-
-    registry.register_material(Loam);
-    registry.register_material(LushGrass);
-    registry.register_material(Oak);
-    registry.register_material(Shale);
-
-    registry.register_block(Rock);
-    registry.register_block(RockSlab);
-    registry.register_block(Soil);
-    registry.register_block(Wood);
-    registry.register_block(Glass);
+    registry.register_defaults();
 
     log::info!(
         "Generating an array of {} textures",
@@ -212,15 +234,24 @@ fn setup_registry(
     let storage_buffer = ShaderStorageBuffer::new(&model_data, RenderAssetUsages::RENDER_WORLD);
     let buffer_handle = buffers.add(storage_buffer);
 
-    let material = materials.add(ChunkMaterial {
+    let material = ChunkMaterial {
         array_texture: texture_handle.clone(),
         ao_factor: 0.6,
+        alpha_cutoff: 0.5,
         model_buffer: buffer_handle,
+        alpha_mode: AlphaMode::Mask(0.5),
+    };
+    let transparent_material = materials.add(ChunkMaterial {
+        alpha_cutoff: 0.0,
+        alpha_mode: AlphaMode::Blend,
+        ..material.clone()
     });
+    let material = materials.add(material);
 
     commands.insert_resource(SharedRegistry(Arc::new(registry)));
     commands.insert_resource(BlockTextureArray {
         handle: texture_handle,
         material,
+        transparent_material,
     });
 }
@@ -1,86 +1,490 @@
 use std::{
-    collections::HashMap,
-    mem,
-    path::PathBuf,
+    collections::{HashMap, HashSet, VecDeque},
+    fs, io, mem,
+    path::{Path, PathBuf},
     sync::Arc,
     time::{Duration, Instant},
 };
 
 use bevy::{
+    ecs::system::SystemParam,
     math::USizeVec3,
+    mesh::Indices,
     prelude::*,
-    tasks::{AsyncComputeTaskPool, Task, futures::check_ready},
+    tasks::{AsyncComputeTaskPool, Task, block_on, futures::check_ready},
 };
 use bevy_egui::{
     EguiContexts, EguiPlugin, EguiPrimaryContextPass,
-    egui::{self, Slider},
+    egui::{self, DragValue, Slider},
 };
 use indexmap::IndexMap;
 
 use crate::{
-    Block, BlockTextureArray, CHUNK_SIZE, ChunkData, ChunkMaterial, Player, RegionManager,
-    Registry, RelevantChunks, SharedRegistry, WorldGenerator, generate_mesh,
+    Aabb, Block, BlockFace, BlockTextureArray, CHUNK_SIZE, ChunkData, ChunkLight, ChunkMaterial,
+    ChunkMeshes, Codec, DEFAULT_SEED, DownsampledChunk, GeneratorSettings, IdMap, Light,
+    LightChunks, LightEngine, LightQueue, LoadShape, Player, PlayerCamera, RegionError,
+    RegionManager, Registry, RelevantChunks, SharedRegistry, TaskBudget, TaskBudgetConfig,
+    TransparentQuads, ViewDistance, WorldGenerator, WorldMetadata, generate_mesh,
 };
 
-pub struct WorldPlugin;
+#[derive(Default)]
+pub struct WorldPlugin {
+    pub config: WorldConfig,
+}
+
+#[derive(Debug, Clone)]
+pub struct WorldConfig {
+    /// The directory that holds every world, each in a subdirectory named after it.
+    pub save_path: PathBuf,
+    /// The world that's opened on startup, which is created if it doesn't exist yet.
+    pub world_name: String,
+    /// The seed for the startup world if it's created, existing worlds keep their own.
+    pub seed: u32,
+    /// Where regions were saved before there were named worlds, which is moved into place as the
+    /// startup world if that doesn't exist yet.
+    pub legacy_region_path: Option<PathBuf>,
+    /// How new region files are compressed. Existing ones keep the codec they were created with,
+    /// so worlds using one that isn't enabled in a build can't be opened by it.
+    pub codec: Codec,
+    pub view_distance: ViewDistance,
+    pub generation_budget: TaskBudgetConfig,
+    pub mesh_budget: TaskBudgetConfig,
+    /// Merge matching faces of full cubes into larger quads, see `generate_mesh`.
+    pub greedy_meshing: bool,
+}
+
+impl Default for WorldConfig {
+    fn default() -> Self {
+        Self {
+            save_path: PathBuf::from("saves"),
+            world_name: "world".to_string(),
+            seed: DEFAULT_SEED,
+            legacy_region_path: Some(PathBuf::from("regions")),
+            codec: Codec::default(),
+            view_distance: ViewDistance::default(),
+            generation_budget: TaskBudgetConfig::default(),
+            mesh_budget: TaskBudgetConfig::default(),
+            greedy_meshing: true,
+        }
+    }
+}
 
 impl Plugin for WorldPlugin {
     fn build(&self, app: &mut App) {
+        let config = &self.config;
+        assert!(
+            config.codec.is_supported(),
+            "the {} codec isn't enabled in this build",
+            config.codec
+        );
+
+        let world_dir = world_dir(&config.save_path, &config.world_name)
+            .expect("the configured world name should be valid");
+
+        if let Some(legacy_dir) = &config.legacy_region_path
+            && adopt_legacy_world(legacy_dir, &world_dir)
+                .expect("failed to move the legacy world into the save path")
+        {
+            log::info!(
+                "Moved the world in {} to {}",
+                legacy_dir.display(),
+                world_dir.display()
+            );
+        }
+
+        let (region_manager, metadata) =
+            open_world(&world_dir, config.seed, config.codec).expect("failed to open the world");
+
         app.add_plugins(EguiPlugin::default())
             .insert_resource(World {
                 center_pos: IVec3::ZERO,
-                region_manager: Arc::new(RegionManager::new(PathBuf::from("regions"))),
-                generator: WorldGenerator::new(),
-                generation_radius: 12,
+                region_manager: Arc::new(region_manager),
+                save_path: config.save_path.clone(),
+                world_name: config.world_name.clone(),
+                world_to_open: None,
+                codec: config.codec,
+                generator: WorldGenerator::with_settings(metadata.seed, metadata.generator),
+                metadata,
+                view_distance: config.view_distance,
+                view_direction: Vec3::NEG_Z,
+                load_offsets: config.view_distance.offsets(),
+                load_cursor: 0,
+                needs_unload: false,
+                generation_budget: TaskBudget::new(config.generation_budget),
+                mesh_budget: TaskBudget::new(config.mesh_budget),
+                greedy_meshing: config.greedy_meshing,
                 generation_tasks: IndexMap::new(),
                 mesh_tasks: IndexMap::new(),
                 chunks: IndexMap::new(),
+                chunks_to_light: VecDeque::new(),
+                blocks_to_relight: Vec::new(),
+                light_queue: LightQueue::default(),
+                relit_chunks: HashSet::new(),
                 chunks_to_save: HashMap::new(),
+                block_changes: Vec::new(),
+                saving_chunks: Arc::new(HashMap::new()),
                 last_save_time: Instant::now(),
+                save_failures: 0,
+                load_retries: IndexMap::new(),
                 save_task: None,
+                last_eviction_time: Instant::now(),
             })
-            .add_systems(Update, (update_world, save_chunks).chain_ignore_deferred())
+            .add_systems(PostStartup, setup_id_map)
+            .add_systems(
+                Update,
+                (
+                    switch_world,
+                    update_task_budgets,
+                    update_view_direction,
+                    update_world,
+                    save_chunks,
+                    evict_regions,
+                )
+                    .chain_ignore_deferred(),
+            )
+            .add_message::<BlockChanged>()
+            .add_message::<ChunkLoaded>()
+            .add_message::<ChunkUnloaded>()
+            .add_message::<ChunkMeshed>()
+            .add_systems(
+                PostUpdate,
+                (
+                    send_block_changes,
+                    sort_transparent_quads.after(TransformSystems::Propagate),
+                ),
+            )
+            .add_systems(Last, save_on_exit)
             .add_systems(EguiPrimaryContextPass, debug_ui);
     }
 }
 
+/// Sent for every block that's changed through [`World::set_block`] or [`World::edit`].
+#[derive(Message, Debug, Clone, Copy)]
+pub struct BlockChanged {
+    pub pos: IVec3,
+    pub old: Option<Block>,
+    pub new: Option<Block>,
+    pub cause: BlockChangeCause,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockChangeCause {
+    /// A player broke or placed the block.
+    Player,
+    /// Game code changed the block, such as when placing a structure.
+    Script,
+}
+
+/// Sent when a chunk's data is loaded, before it has a mesh.
+#[derive(Message, Debug, Clone, Copy)]
+pub struct ChunkLoaded {
+    pub chunk_pos: IVec3,
+}
+
+#[derive(Message, Debug, Clone, Copy)]
+pub struct ChunkUnloaded {
+    pub chunk_pos: IVec3,
+}
+
+/// Sent whenever a chunk's mesh is brought up to date, including after edits.
+#[derive(Message, Debug, Clone, Copy)]
+pub struct ChunkMeshed {
+    pub chunk_pos: IVec3,
+    /// The entity holding the mesh, or `None` if the chunk has nothing to draw.
+    pub entity: Option<Entity>,
+}
+
+/// Queue lengths and task budgets, for tuning how fast the world streams in.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WorldMetrics {
+    pub loaded_chunks: usize,
+    pub generation_tasks: usize,
+    pub generation_budget: usize,
+    pub mesh_tasks: usize,
+    pub mesh_budget: usize,
+    /// Loaded chunks that haven't been lit yet.
+    pub chunks_to_light: usize,
+    /// Loaded chunks whose mesh is missing or out of date.
+    pub chunks_to_mesh: usize,
+    /// Edited or generated chunks that haven't reached disk yet.
+    pub chunks_to_save: usize,
+    /// Periodic saves that failed in a row. Once this reaches `MAX_SAVE_FAILURES`, chunks are
+    /// only saved by `World::save_now`.
+    pub save_failures: u32,
+    pub loaded_regions: usize,
+}
+
+#[derive(SystemParam)]
+struct ChunkMessages<'w> {
+    loaded: MessageWriter<'w, ChunkLoaded>,
+    unloaded: MessageWriter<'w, ChunkUnloaded>,
+    meshed: MessageWriter<'w, ChunkMeshed>,
+}
+
 #[derive(Resource)]
 pub struct World {
     center_pos: IVec3,
     region_manager: Arc<RegionManager>,
+    save_path: PathBuf,
+    world_name: String,
+    world_to_open: Option<String>,
+    /// Used for the region files of every world opened, see `WorldConfig::codec`.
+    codec: Codec,
     generator: WorldGenerator,
-    generation_radius: i32,
+    metadata: WorldMetadata,
+    view_distance: ViewDistance,
+    /// Which way the camera is looking, so chunks in view can be loaded and meshed first.
+    view_direction: Vec3,
+    /// Every offset within the view distance, closest first.
+    load_offsets: Vec<IVec3>,
+    /// All of `load_offsets` before this index are loaded or being generated. Generation is only
+    /// dropped for chunks out of range, or when switching worlds, which resets this.
+    load_cursor: usize,
+    /// Set when chunks may have gone out of range, so loaded chunks don't have to be checked
+    /// every frame.
+    needs_unload: bool,
+    generation_budget: TaskBudget,
+    mesh_budget: TaskBudget,
+    greedy_meshing: bool,
     generation_tasks: IndexMap<IVec3, Task<GenerationResult>>,
-    mesh_tasks: IndexMap<IVec3, Task<Option<Mesh>>>,
+    mesh_tasks: IndexMap<IVec3, Task<ChunkMeshes>>,
     chunks: IndexMap<IVec3, Chunk>,
+    /// Loaded chunks waiting to be lit, in the order they were loaded.
+    chunks_to_light: VecDeque<IVec3>,
+    /// Edited blocks whose surroundings need relighting.
+    blocks_to_relight: Vec<IVec3>,
+    /// Light that didn't finish spreading within the last frame's budget.
+    light_queue: LightQueue,
+    /// Chunks whose light changed while it's still spreading. They're remeshed once the queued
+    /// light can't reach them anymore, rather than meshed half lit.
+    relit_chunks: HashSet<IVec3>,
+    /// Edited chunks that haven't been written yet. Together with `saving_chunks` this acts as a
+    /// write-back cache, so unloaded chunks are loaded from here rather than from stale copies on
+    /// disk.
     chunks_to_save: HashMap<IVec3, ChunkData>,
-    save_task: Option<Task<()>>,
+    /// The chunks being written by `save_task`.
+    saving_chunks: Arc<HashMap<IVec3, ChunkData>>,
+    save_task: Option<Task<Result<(), RegionError>>>,
+    /// Changes waiting to be sent as messages, since edits can happen from any system.
+    block_changes: Vec<BlockChanged>,
     last_save_time: Instant,
+    /// How many periodic saves in a row have failed.
+    save_failures: u32,
+    /// Chunks that failed to load, which are read-only stand-ins until a retry succeeds.
+    load_retries: IndexMap<IVec3, LoadRetry>,
+    last_eviction_time: Instant,
 }
 
 impl World {
     pub fn is_visible_chunk(&self, chunk_pos: IVec3) -> bool {
-        let horizontal_distance = Vec2::new(chunk_pos.x as f32, chunk_pos.z as f32).distance(
-            Vec2::new(self.center_pos.x as f32, self.center_pos.z as f32),
-        );
+        self.view_distance.contains(chunk_pos - self.center_pos)
+    }
 
-        if horizontal_distance > self.generation_radius as f32 {
-            return false;
+    pub fn metrics(&self) -> WorldMetrics {
+        WorldMetrics {
+            loaded_chunks: self.chunks.len(),
+            generation_tasks: self.generation_tasks.len(),
+            generation_budget: self.generation_budget.limit(),
+            mesh_tasks: self.mesh_tasks.len(),
+            mesh_budget: self.mesh_budget.limit(),
+            chunks_to_light: self.chunks_to_light.len(),
+            chunks_to_mesh: self.chunks.values().filter(|chunk| chunk.dirty).count(),
+            chunks_to_save: self.chunks_to_save.len() + self.saving_chunks.len(),
+            save_failures: self.save_failures,
+            loaded_regions: self.region_manager.loaded_region_count(),
         }
+    }
 
-        let vertical_distance = (chunk_pos.y as f32 - self.center_pos.y as f32).abs();
+    pub fn generation_budget(&self) -> &TaskBudget {
+        &self.generation_budget
+    }
 
-        if vertical_distance > self.generation_radius as f32 {
-            return false;
+    pub fn generation_budget_mut(&mut self) -> &mut TaskBudget {
+        &mut self.generation_budget
+    }
+
+    pub fn mesh_budget(&self) -> &TaskBudget {
+        &self.mesh_budget
+    }
+
+    pub fn mesh_budget_mut(&mut self) -> &mut TaskBudget {
+        &mut self.mesh_budget
+    }
+
+    pub fn greedy_meshing(&self) -> bool {
+        self.greedy_meshing
+    }
+
+    /// Switches greedy meshing on or off, remeshing every loaded chunk.
+    pub fn set_greedy_meshing(&mut self, greedy_meshing: bool) {
+        if greedy_meshing != self.greedy_meshing {
+            self.greedy_meshing = greedy_meshing;
+
+            let chunk_positions = self.chunks.keys().copied().collect::<Vec<_>>();
+
+            for chunk_pos in chunk_positions {
+                self.force_remesh(chunk_pos);
+            }
+        }
+    }
+
+    pub fn view_distance(&self) -> ViewDistance {
+        self.view_distance
+    }
+
+    pub fn set_view_distance(&mut self, view_distance: ViewDistance) {
+        if view_distance == self.view_distance {
+            return;
         }
 
-        true
+        let old_view_distance = mem::replace(&mut self.view_distance, view_distance);
+        self.remesh_lod_changes(self.center_pos, old_view_distance);
+
+        // Only LOD distances changing doesn't affect which chunks are loaded
+        if view_distance.horizontal != old_view_distance.horizontal
+            || view_distance.vertical != old_view_distance.vertical
+            || view_distance.shape != old_view_distance.shape
+        {
+            self.load_offsets = view_distance.offsets();
+            self.reset_loading();
+        }
+    }
+
+    /// The LOD level the chunk is meshed at, see `ViewDistance::lod_level`.
+    pub fn lod_level(&self, chunk_pos: IVec3) -> usize {
+        self.view_distance.lod_level(chunk_pos - self.center_pos)
+    }
+
+    /// How far from the border of a chunk its blocks can still show up in a neighbor's mesh,
+    /// since meshes downsample their neighbors to their own LOD level. See `border_neighbors`.
+    pub fn border_scale(&self, chunk_pos: IVec3) -> usize {
+        let lod_level = get_neighbors(chunk_pos)
+            .into_iter()
+            .chain([chunk_pos])
+            .map(|pos| self.lod_level(pos))
+            .max()
+            .unwrap_or(0);
+
+        1 << lod_level
+    }
+
+    pub fn metadata(&self) -> &WorldMetadata {
+        &self.metadata
+    }
+
+    pub fn world_name(&self) -> &str {
+        &self.world_name
+    }
+
+    /// Lists the names of the saved worlds, in alphabetical order.
+    pub fn list_worlds(&self) -> Result<Vec<String>, RegionError> {
+        let entries = match fs::read_dir(&self.save_path) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(error) => return Err(error.into()),
+        };
+
+        let mut worlds = Vec::new();
+
+        for entry in entries {
+            let entry = entry?;
+
+            if WorldMetadata::exists(&entry.path())
+                && let Some(name) = entry.file_name().to_str()
+            {
+                worlds.push(name.to_string());
+            }
+        }
+
+        worlds.sort();
+
+        Ok(worlds)
+    }
+
+    /// Creates a new world without opening it.
+    pub fn create_world(&self, name: &str, seed: u32) -> Result<(), RegionError> {
+        let dir = world_dir(&self.save_path, name)?;
+
+        if dir.try_exists()? {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("world {name} already exists"),
+            )
+            .into());
+        }
+
+        fs::create_dir_all(&dir)?;
+        WorldMetadata::new(seed, GeneratorSettings::default()).save(&dir)?;
+
+        Ok(())
+    }
+
+    /// Deletes a world and all of its saved chunks. The open world can't be deleted.
+    pub fn delete_world(&self, name: &str) -> Result<(), RegionError> {
+        let dir = world_dir(&self.save_path, name)?;
+
+        if name == self.world_name || self.world_to_open.as_deref() == Some(name) {
+            return Err(io::Error::new(
+                io::ErrorKind::ResourceBusy,
+                format!("world {name} is currently open"),
+            )
+            .into());
+        }
+
+        if !WorldMetadata::exists(&dir) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("world {name} doesn't exist"),
+            )
+            .into());
+        }
+
+        fs::remove_dir_all(dir)?;
+
+        Ok(())
+    }
+
+    /// Opens another world at the start of the next update, once the current one has been saved.
+    pub fn switch_world(&mut self, name: &str) {
+        self.world_to_open = Some(name.to_string());
+    }
+
+    /// Writes every pending chunk to disk, waiting for the save in progress first. Unlike the
+    /// periodic save, this blocks until the chunks are on disk.
+    pub fn save_now(&mut self) -> Result<(), RegionError> {
+        if let Some(task) = self.save_task.take() {
+            let result = block_on(task);
+
+            if let Err(error) = self.finish_save(result) {
+                log::warn!("Failed to save chunks, retrying them now: {error}");
+            }
+        }
+
+        let chunks = mem::take(&mut self.chunks_to_save);
+        self.last_save_time = Instant::now();
+
+        if let Err(error) = self.region_manager.save_chunks(&chunks) {
+            self.chunks_to_save = chunks;
+            return Err(error);
+        }
+
+        self.region_manager.flush()?;
+        self.save_failures = 0;
+
+        Ok(())
     }
 
     pub fn get_chunk_data(&self, chunk_pos: IVec3) -> Option<ChunkData> {
         self.chunks.get(&chunk_pos).map(|chunk| chunk.data.clone())
     }
 
+    /// The downsampled copies of a chunk, shared by the mesh tasks that read it.
+    pub fn get_downsampled(&self, chunk_pos: IVec3) -> Option<Arc<DownsampledChunk>> {
+        self.chunks
+            .get(&chunk_pos)
+            .map(|chunk| chunk.downsampled.clone())
+    }
+
     pub fn get_block(&self, world_pos: IVec3) -> Option<Block> {
         let chunk_pos = Self::chunk_pos(world_pos);
         let local_pos = Self::local_pos(world_pos);
@@ -88,30 +492,77 @@ impl World {
         chunk.data.get_block(local_pos)
     }
 
-    pub fn set_block(&mut self, world_pos: IVec3, block: Option<Block>) {
-        let chunk_pos = Self::chunk_pos(world_pos);
-        let local_pos = Self::local_pos(world_pos);
+    /// The light of a chunk, once it has been lit.
+    pub fn get_chunk_light(&self, chunk_pos: IVec3) -> Option<ChunkLight> {
+        self.chunks
+            .get(&chunk_pos)
+            .filter(|chunk| chunk.lit)
+            .map(|chunk| chunk.light.clone())
+    }
 
-        let Some(chunk) = self.chunks.get_mut(&chunk_pos) else {
-            return;
+    pub fn get_light(&self, world_pos: IVec3) -> Option<Light> {
+        let (_, chunk_light) = self.get_chunk(Self::chunk_pos(world_pos))?;
+        Some(chunk_light.get(Self::local_pos(world_pos)))
+    }
+
+    /// Sets a single block, remeshing its chunk and only the neighbors that touch it.
+    pub fn set_block(&mut self, world_pos: IVec3, block: Option<Block>, cause: BlockChangeCause) {
+        self.edit(cause, |editor| editor.set_block(world_pos, block));
+    }
+
+    /// Applies many block changes at once. Each edited chunk is saved and remeshed once at the
+    /// end, along with only the neighbors whose borders were touched. Light around the edited
+    /// blocks is updated before the next meshes are built.
+    pub fn edit<R>(&mut self, cause: BlockChangeCause, f: impl FnOnce(&mut WorldEditor) -> R) -> R {
+        let mut editor = WorldEditor {
+            world: self,
+            cause,
+            edited_chunks: HashSet::new(),
+            edited_blocks: Vec::new(),
+            chunks_to_remesh: HashSet::new(),
+            border_scales: HashMap::new(),
         };
 
-        Arc::make_mut(&mut chunk.data).set_block(local_pos, block);
-        self.chunks_to_save.insert(chunk_pos, chunk.data.clone());
+        let result = f(&mut editor);
 
-        self.force_remesh(chunk_pos);
+        let WorldEditor {
+            edited_chunks,
+            edited_blocks,
+            chunks_to_remesh,
+            ..
+        } = editor;
 
-        for neighbor_pos in get_neighbors(chunk_pos) {
-            self.force_remesh(neighbor_pos);
+        self.blocks_to_relight.extend(edited_blocks);
+
+        for chunk_pos in edited_chunks {
+            self.chunks_to_save
+                .insert(chunk_pos, self.chunks[&chunk_pos].data.clone());
+        }
+
+        for chunk_pos in chunks_to_remesh {
+            self.force_remesh(chunk_pos);
         }
+
+        result
+    }
+
+    /// Sets every block between `min` and `max`, inclusive, in loaded chunks.
+    pub fn fill_region(
+        &mut self,
+        min: IVec3,
+        max: IVec3,
+        block: Option<Block>,
+        cause: BlockChangeCause,
+    ) {
+        self.edit(cause, |editor| editor.fill_region(min, max, block));
     }
 
     pub fn chunk_sort_key(&self, chunk_pos: IVec3) -> (i32, i32) {
-        (
-            IVec3::new(chunk_pos.x, self.center_pos.y, chunk_pos.z)
-                .distance_squared(self.center_pos),
-            (chunk_pos.y - self.center_pos.y).abs(),
-        )
+        ViewDistance::sort_key_towards(chunk_pos - self.center_pos, self.view_direction)
+    }
+
+    pub fn view_direction(&self) -> Vec3 {
+        self.view_direction
     }
 
     pub fn chunk_pos(world_pos: IVec3) -> IVec3 {
@@ -129,7 +580,44 @@ impl World {
 
     fn update_center_pos(&mut self, position: Vec3) {
         let player_world_pos = IVec3::new(position.x as i32, position.y as i32, position.z as i32);
-        self.center_pos = World::chunk_pos(player_world_pos);
+        let center_pos = World::chunk_pos(player_world_pos);
+
+        if center_pos != self.center_pos {
+            let old_center_pos = mem::replace(&mut self.center_pos, center_pos);
+            self.remesh_lod_changes(old_center_pos, self.view_distance);
+            self.reset_loading();
+        }
+    }
+
+    /// Remeshes chunks whose LOD level changed, along with their neighbors since the seams
+    /// between them moved too.
+    fn remesh_lod_changes(&mut self, old_center_pos: IVec3, old_view_distance: ViewDistance) {
+        let changed_chunks = self
+            .chunks
+            .keys()
+            .copied()
+            .filter(|&chunk_pos| {
+                old_view_distance.lod_level(chunk_pos - old_center_pos) != self.lod_level(chunk_pos)
+            })
+            .collect::<Vec<_>>();
+
+        for chunk_pos in changed_chunks {
+            self.force_remesh(chunk_pos);
+
+            for neighbor_pos in get_neighbors(chunk_pos) {
+                self.force_remesh(neighbor_pos);
+            }
+        }
+    }
+
+    fn update_view_direction(&mut self, direction: Vec3) {
+        self.view_direction = direction.normalize_or(Vec3::NEG_Z);
+    }
+
+    /// Makes the next update look for chunks to load and unload from scratch.
+    fn reset_loading(&mut self) {
+        self.load_cursor = 0;
+        self.needs_unload = true;
     }
 
     fn chunks_to_unload(&self) -> Vec<IVec3> {
@@ -140,6 +628,78 @@ impl World {
             .collect()
     }
 
+    /// Returns the latest version of a chunk that hasn't been written to disk yet.
+    fn cached_chunk(&self, chunk_pos: IVec3) -> Option<ChunkData> {
+        self.chunks_to_save
+            .get(&chunk_pos)
+            .or_else(|| self.saving_chunks.get(&chunk_pos))
+            .cloned()
+    }
+
+    /// Clears the chunks of a finished save, queueing them again if it failed. Chunks that were
+    /// edited since keep their newer data.
+    fn finish_save(&mut self, result: Result<(), RegionError>) -> Result<(), RegionError> {
+        let chunks = mem::take(&mut self.saving_chunks);
+
+        if result.is_err() {
+            for (chunk_pos, chunk_data) in chunks.iter() {
+                self.chunks_to_save
+                    .entry(*chunk_pos)
+                    .or_insert_with(|| chunk_data.clone());
+            }
+        }
+
+        result
+    }
+
+    /// Swaps in the data of a chunk that was loaded after its stand-in was generated, relighting
+    /// and remeshing around every block that differs.
+    fn replace_chunk_data(&mut self, chunk_pos: IVec3, chunk_data: ChunkData) {
+        let Some(chunk) = self.chunks.get_mut(&chunk_pos) else {
+            return;
+        };
+
+        let old_data = mem::replace(&mut chunk.data, chunk_data);
+        chunk.clear_downsampled();
+
+        if chunk.lit {
+            let origin = chunk_pos * CHUNK_SIZE as i32;
+            let size = CHUNK_SIZE as i32;
+
+            for (index, (old, new)) in old_data.iter().zip(chunk.data.iter()).enumerate() {
+                if old != new {
+                    let index = index as i32;
+                    let local_pos =
+                        IVec3::new(index % size, index / size % size, index / (size * size));
+                    self.blocks_to_relight.push(origin + local_pos);
+                }
+            }
+        }
+
+        self.force_remesh(chunk_pos);
+
+        for neighbor_pos in get_neighbors(chunk_pos) {
+            self.force_remesh(neighbor_pos);
+        }
+    }
+
+    /// Marks the next loaded chunk that's waiting for light as lit, so light can spread into it.
+    fn next_chunk_to_light(&mut self) -> Option<IVec3> {
+        while let Some(chunk_pos) = self.chunks_to_light.pop_front() {
+            // Chunks may have been unloaded since, or loaded again and already lit
+            if let Some(chunk) = self.chunks.get_mut(&chunk_pos)
+                && !chunk.lit
+            {
+                chunk.lit = true;
+                chunk.clear_downsampled();
+
+                return Some(chunk_pos);
+            }
+        }
+
+        None
+    }
+
     fn force_remesh(&mut self, chunk_pos: IVec3) {
         if let Some(chunk) = self.chunks.get_mut(&chunk_pos) {
             chunk.dirty = true;
@@ -149,10 +709,234 @@ impl World {
     }
 }
 
+/// Read-only access to the world for other plugins.
+#[derive(SystemParam)]
+pub struct WorldQuery<'w> {
+    world: Res<'w, World>,
+    shared_registry: Res<'w, SharedRegistry>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkState {
+    Unloaded,
+    /// The chunk is being read from disk or generated.
+    Loading,
+    /// The chunk's data is loaded, but its mesh is missing or out of date.
+    Loaded,
+    /// The chunk's data is loaded and its mesh is up to date.
+    Meshed,
+}
+
+impl WorldQuery<'_> {
+    /// The chunk that chunks are currently being loaded around.
+    pub fn center_pos(&self) -> IVec3 {
+        self.world.center_pos
+    }
+
+    pub fn loaded_chunks(&self) -> impl Iterator<Item = IVec3> + '_ {
+        self.world.chunks.keys().copied()
+    }
+
+    pub fn chunk_state(&self, chunk_pos: IVec3) -> ChunkState {
+        match self.world.chunks.get(&chunk_pos) {
+            Some(chunk) if chunk.dirty => ChunkState::Loaded,
+            Some(_) => ChunkState::Meshed,
+            None if self.world.generation_tasks.contains_key(&chunk_pos) => ChunkState::Loading,
+            None => ChunkState::Unloaded,
+        }
+    }
+
+    pub fn is_chunk_loaded(&self, chunk_pos: IVec3) -> bool {
+        self.world.chunks.contains_key(&chunk_pos)
+    }
+
+    /// Whether every chunk containing a block between `min` and `max`, inclusive, is loaded.
+    pub fn is_region_loaded(&self, min: IVec3, max: IVec3) -> bool {
+        let min_chunk = World::chunk_pos(min.min(max));
+        let max_chunk = World::chunk_pos(min.max(max));
+
+        (min_chunk.z..=max_chunk.z).all(|z| {
+            (min_chunk.y..=max_chunk.y).all(|y| {
+                (min_chunk.x..=max_chunk.x).all(|x| self.is_chunk_loaded(IVec3::new(x, y, z)))
+            })
+        })
+    }
+
+    pub fn get_block(&self, world_pos: IVec3) -> Option<Block> {
+        self.world.get_block(world_pos)
+    }
+
+    pub fn get_chunk_data(&self, chunk_pos: IVec3) -> Option<ChunkData> {
+        self.world.get_chunk_data(chunk_pos)
+    }
+
+    /// The light at a block, or `None` if its chunk isn't loaded or lit yet.
+    pub fn get_light(&self, world_pos: IVec3) -> Option<Light> {
+        self.world.get_light(world_pos)
+    }
+
+    /// Whether the block at `world_pos` has a collision box.
+    pub fn is_solid(&self, world_pos: IVec3) -> bool {
+        self.get_block(world_pos).is_some_and(|block| {
+            self.shared_registry
+                .0
+                .block_type(block.id)
+                .get_aabb(block.data)
+                .is_some()
+        })
+    }
+
+    /// Finds the highest solid block in a column, looking only at loaded chunks.
+    pub fn highest_solid_block(&self, x: i32, z: i32) -> Option<IVec3> {
+        let center_y = self.world.center_pos.y;
+        let radius = self.world.view_distance.vertical;
+        let chunk_pos = World::chunk_pos(IVec3::new(x, 0, z));
+
+        for chunk_y in (center_y - radius..=center_y + radius).rev() {
+            let Some(chunk) = self
+                .world
+                .chunks
+                .get(&IVec3::new(chunk_pos.x, chunk_y, chunk_pos.z))
+            else {
+                continue;
+            };
+
+            if chunk.data.is_empty() {
+                continue;
+            }
+
+            let bottom = chunk_y * CHUNK_SIZE as i32;
+
+            for y in (bottom..bottom + CHUNK_SIZE as i32).rev() {
+                if self.is_solid(IVec3::new(x, y, z)) {
+                    return Some(IVec3::new(x, y, z));
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Returns every loaded block that overlaps `aabb`, skipping air.
+    pub fn blocks_in_aabb(&self, aabb: Aabb) -> impl Iterator<Item = (IVec3, Block)> + '_ {
+        let min = aabb.min().floor().as_ivec3();
+        let max = aabb.max().ceil().as_ivec3() - IVec3::ONE;
+
+        (min.z..=max.z).flat_map(move |z| {
+            (min.y..=max.y).flat_map(move |y| {
+                (min.x..=max.x).filter_map(move |x| {
+                    let world_pos = IVec3::new(x, y, z);
+                    self.get_block(world_pos).map(|block| (world_pos, block))
+                })
+            })
+        })
+    }
+}
+
+/// Batches block changes made through [`World::edit`].
+pub struct WorldEditor<'a> {
+    world: &'a mut World,
+    cause: BlockChangeCause,
+    edited_chunks: HashSet<IVec3>,
+    edited_blocks: Vec<IVec3>,
+    chunks_to_remesh: HashSet<IVec3>,
+    /// The `border_scale` of each edited chunk, which looks at all of its neighbors.
+    border_scales: HashMap<IVec3, usize>,
+}
+
+impl WorldEditor<'_> {
+    pub fn get_block(&self, world_pos: IVec3) -> Option<Block> {
+        self.world.get_block(world_pos)
+    }
+
+    /// Sets a block, returning whether anything changed. Blocks in unloaded chunks, or in chunks
+    /// that failed to load and are waiting for a retry, are ignored.
+    pub fn set_block(&mut self, world_pos: IVec3, block: Option<Block>) -> bool {
+        let chunk_pos = World::chunk_pos(world_pos);
+        let local_pos = World::local_pos(world_pos);
+
+        let Some(chunk) = self.world.chunks.get_mut(&chunk_pos) else {
+            return false;
+        };
+
+        // Saving edits would overwrite whatever is on disk with the generated stand-in
+        if chunk.read_only {
+            return false;
+        }
+
+        let old = chunk.data.get_block(local_pos);
+
+        if old == block {
+            return false;
+        }
+
+        // Only the first edit to a chunk can clone it, since nothing else holds it until the end
+        Arc::make_mut(&mut chunk.data).set_block(local_pos, block);
+        chunk.clear_downsampled();
+
+        self.world.block_changes.push(BlockChanged {
+            pos: world_pos,
+            old,
+            new: block,
+            cause: self.cause,
+        });
+
+        self.edited_chunks.insert(chunk_pos);
+        self.edited_blocks.push(world_pos);
+
+        let scale = *self
+            .border_scales
+            .entry(chunk_pos)
+            .or_insert_with(|| self.world.border_scale(chunk_pos));
+        self.chunks_to_remesh.extend(
+            border_neighbors(local_pos, scale)
+                .into_iter()
+                .map(|offset| chunk_pos + offset),
+        );
+
+        true
+    }
+
+    /// Sets every block between `min` and `max`, inclusive, in loaded chunks.
+    pub fn fill_region(&mut self, min: IVec3, max: IVec3, block: Option<Block>) {
+        let (min, max) = (min.min(max), min.max(max));
+
+        for z in min.z..=max.z {
+            for y in min.y..=max.y {
+                for x in min.x..=max.x {
+                    self.set_block(IVec3::new(x, y, z), block);
+                }
+            }
+        }
+    }
+}
+
 struct Chunk {
     data: ChunkData,
+    /// Set when the chunk failed to load and was generated instead, so that it can't be edited
+    /// and saved over the chunk on disk.
+    read_only: bool,
+    light: ChunkLight,
+    /// Whether light has been spread into the chunk. Until then it's dark and left out of
+    /// lighting, and neither it nor its neighbors are meshed.
+    lit: bool,
     dirty: bool,
-    entity: Entity,
+    /// Only spawned once the chunk has a mesh, since most chunks are empty or hidden.
+    entity: Option<Entity>,
+    /// A child of `entity` holding the transparent mesh, if the chunk has transparent faces.
+    transparent_entity: Option<Entity>,
+    downsampled: Arc<DownsampledChunk>,
+}
+
+impl Chunk {
+    /// Drops the downsampled copies after the chunk or its light changed. Mesh tasks may still
+    /// fill in the old ones, so those are only reused if nothing else holds them.
+    fn clear_downsampled(&mut self) {
+        match Arc::get_mut(&mut self.downsampled) {
+            Some(downsampled) => downsampled.clear(),
+            None => self.downsampled = Arc::default(),
+        }
+    }
 }
 
 fn update_world(
@@ -162,36 +946,70 @@ fn update_world(
     mut meshes: ResMut<Assets<Mesh>>,
     texture_array: Res<BlockTextureArray>,
     shared_registry: Res<SharedRegistry>,
+    mut chunk_messages: ChunkMessages,
 ) {
     let player = player.single().unwrap();
 
     world.update_center_pos(player.translation());
 
-    unload_far_chunks(&mut commands, &mut world);
-    load_near_chunks(
-        &mut commands,
-        &mut world,
-        texture_array.material.clone(),
-        &shared_registry.0,
-    );
+    unload_far_chunks(&mut commands, &mut world, &mut chunk_messages);
+    load_near_chunks(&mut world, &shared_registry.0, &mut chunk_messages);
+    retry_failed_loads(&mut world);
+    update_light(&mut world, &shared_registry.0);
     regenerate_meshes(
         &mut commands,
         &mut world,
         &mut meshes,
+        &texture_array,
         shared_registry.0.clone(),
+        &mut chunk_messages,
     );
 }
 
-fn unload_far_chunks(commands: &mut Commands, world: &mut World) {
+/// How many of the closest chunks left to load are ordered by the view direction each frame, so
+/// the whole load order doesn't have to be re-sorted whenever the camera turns.
+const LOAD_CANDIDATES: usize = 256;
+
+fn update_view_direction(
+    mut world: ResMut<World>,
+    camera: Query<&GlobalTransform, With<PlayerCamera>>,
+) {
+    if let Ok(camera) = camera.single() {
+        world.update_view_direction(camera.forward().into());
+    }
+}
+
+fn update_task_budgets(mut world: ResMut<World>, time: Res<Time<Real>>) {
+    let frame_time = time.delta();
+
+    world.generation_budget.update(frame_time);
+    world.mesh_budget.update(frame_time);
+}
+
+fn unload_far_chunks(
+    commands: &mut Commands,
+    world: &mut World,
+    chunk_messages: &mut ChunkMessages,
+) {
+    if !mem::take(&mut world.needs_unload) {
+        return;
+    }
+
     let chunks_to_unload = world.chunks_to_unload();
 
     for chunk_pos in chunks_to_unload {
-        if let Some(chunk) = world.chunks.swap_remove(&chunk_pos) {
-            commands.entity(chunk.entity).despawn();
+        if let Some(entity) = world
+            .chunks
+            .swap_remove(&chunk_pos)
+            .and_then(|chunk| chunk.entity)
+        {
+            commands.entity(entity).despawn();
         }
 
         world.mesh_tasks.swap_remove(&chunk_pos);
         world.generation_tasks.swap_remove(&chunk_pos);
+        world.load_retries.swap_remove(&chunk_pos);
+        chunk_messages.unloaded.write(ChunkUnloaded { chunk_pos });
 
         for neighbor_pos in get_neighbors(chunk_pos) {
             world.force_remesh(neighbor_pos);
@@ -200,39 +1018,56 @@ fn unload_far_chunks(commands: &mut Commands, world: &mut World) {
 }
 
 fn load_near_chunks(
-    commands: &mut Commands,
     world: &mut World,
-    material: Handle<ChunkMaterial>,
     registry: &Arc<Registry>,
+    chunk_messages: &mut ChunkMessages,
 ) {
     let task_pool = AsyncComputeTaskPool::get();
 
-    let mut chunks_to_generate = Vec::new();
-
-    // Look for visible chunks in a cube around the player (not all of these are visible)
-    for x in -world.generation_radius..=world.generation_radius {
-        for y in -world.generation_radius..=world.generation_radius {
-            for z in -world.generation_radius..=world.generation_radius {
-                let chunk_pos = world.center_pos + IVec3::new(x, y, z);
-
-                // Don't load chunks multiple times
-                if world.is_visible_chunk(chunk_pos)
-                    && !world.chunks.contains_key(&chunk_pos)
-                    && !world.generation_tasks.contains_key(&chunk_pos)
-                {
-                    chunks_to_generate.push(chunk_pos);
-                }
-            }
-        }
+    let mut exceeded = false;
+    let mut results = HashMap::new();
+    let is_loading = |world: &World, chunk_pos| {
+        world.chunks.contains_key(&chunk_pos) || world.generation_tasks.contains_key(&chunk_pos)
+    };
+
+    // Walk outwards from the player, starting after the chunks that are already loaded or being
+    // loaded. Chunks still generating are tracked by their tasks, so they aren't scanned again
+    while let Some(&offset) = world.load_offsets.get(world.load_cursor)
+        && is_loading(world, world.center_pos + offset)
+    {
+        world.load_cursor += 1;
     }
 
-    // Sort chunks by distance from the player
-    chunks_to_generate.sort_by_key(|chunk_pos| world.chunk_sort_key(*chunk_pos));
-
-    let mut exceeded = false;
+    // Of the closest chunks left to load, the ones in view go first
+    let view_direction = world.view_direction;
+    let mut candidates = world.load_offsets[world.load_cursor..]
+        .iter()
+        .take(LOAD_CANDIDATES)
+        .copied()
+        .filter(|&offset| !is_loading(world, world.center_pos + offset))
+        .collect::<Vec<_>>();
+    candidates.sort_by_cached_key(|&offset| ViewDistance::sort_key_towards(offset, view_direction));
+
+    for offset in candidates {
+        let chunk_pos = world.center_pos + offset;
+
+        // Chunks with unsaved edits are newer than what's on disk, and are already queued to save
+        if let Some(chunk_data) = world.cached_chunk(chunk_pos) {
+            results.insert(
+                chunk_pos,
+                GenerationResult {
+                    chunk_data,
+                    needs_saving: false,
+                    load_failed: false,
+                },
+            );
+            continue;
+        }
 
-    for chunk_pos in chunks_to_generate {
-        if world.generation_tasks.len() >= 16 {
+        if !world
+            .generation_budget
+            .has_room(world.generation_tasks.len())
+        {
             exceeded = true;
             break;
         }
@@ -250,8 +1085,6 @@ fn load_near_chunks(
 
     let has_generations = !world.generation_tasks.is_empty();
 
-    let mut results = HashMap::new();
-
     // Collect generated chunks, and remove the tasks from the map
     world
         .generation_tasks
@@ -270,19 +1103,6 @@ fn load_near_chunks(
             world.force_remesh(neighbor_pos);
         }
 
-        // Add the entity to the world
-        let entity = commands
-            .spawn((
-                Transform::from_xyz(
-                    chunk_pos.x as f32 * CHUNK_SIZE as f32,
-                    chunk_pos.y as f32 * CHUNK_SIZE as f32,
-                    chunk_pos.z as f32 * CHUNK_SIZE as f32,
-                ),
-                Visibility::Visible,
-                MeshMaterial3d(material.clone()),
-            ))
-            .id();
-
         // Insert the data and queue it for meshing
         if result.needs_saving {
             world
@@ -290,14 +1110,28 @@ fn load_near_chunks(
                 .insert(chunk_pos, result.chunk_data.clone());
         }
 
+        if result.load_failed {
+            world
+                .load_retries
+                .insert(chunk_pos, LoadRetry::after_failures(1));
+        }
+
         world.chunks.insert(
             chunk_pos,
             Chunk {
                 data: result.chunk_data,
+                read_only: result.load_failed,
+                light: ChunkLight::default(),
+                lit: false,
                 dirty: true,
-                entity,
+                entity: None,
+                transparent_entity: None,
+                downsampled: Arc::default(),
             },
         );
+
+        world.chunks_to_light.push_back(chunk_pos);
+        chunk_messages.loaded.write(ChunkLoaded { chunk_pos });
     }
 
     if has_generations && world.generation_tasks.is_empty() && !exceeded {
@@ -308,11 +1142,94 @@ fn load_near_chunks(
     }
 }
 
+/// Lighting a chunk takes a few milliseconds, so only this much time is spent on it per frame.
+const LIGHT_TIME_BUDGET: Duration = Duration::from_millis(4);
+
+/// Relights around edited blocks, then lights newly loaded chunks, until the time budget runs
+/// out. Light that's still spreading then carries on next frame, and every chunk whose light
+/// changed is remeshed once it has settled.
+fn update_light(world: &mut World, registry: &Registry) {
+    let deadline = Instant::now() + LIGHT_TIME_BUDGET;
+    let mut queue = mem::take(&mut world.light_queue);
+    let mut blocks_to_relight = mem::take(&mut world.blocks_to_relight);
+    let mut chunk_to_light = None;
+
+    loop {
+        let mut light_engine = LightEngine::resume(world, registry, queue);
+        light_engine.update_blocks(&mem::take(&mut blocks_to_relight));
+
+        if let Some(chunk_pos) = chunk_to_light {
+            light_engine.light_chunk(chunk_pos);
+        }
+
+        let settled = light_engine.propagate(Some(deadline));
+        let changed_chunks;
+        (queue, changed_chunks) = light_engine.into_parts();
+
+        for chunk_pos in &changed_chunks {
+            if let Some(chunk) = world.chunks.get_mut(chunk_pos) {
+                chunk.clear_downsampled();
+            }
+        }
+
+        world.relit_chunks.extend(changed_chunks);
+
+        if !settled || Instant::now() >= deadline {
+            break;
+        }
+
+        chunk_to_light = world.next_chunk_to_light();
+
+        if chunk_to_light.is_none() {
+            break;
+        }
+    }
+
+    world.light_queue = queue;
+
+    if world.relit_chunks.is_empty() {
+        return;
+    }
+
+    let pending_light = world.light_queue.pending();
+    let settled_chunks = world
+        .relit_chunks
+        .extract_if(|&chunk_pos| !pending_light.may_change(chunk_pos))
+        .collect::<Vec<_>>();
+
+    for chunk_pos in settled_chunks {
+        world.force_remesh(chunk_pos);
+    }
+}
+
+impl LightChunks for World {
+    fn get_chunk(&self, chunk_pos: IVec3) -> Option<(&ChunkData, &ChunkLight)> {
+        self.chunks
+            .get(&chunk_pos)
+            .filter(|chunk| chunk.lit)
+            .map(|chunk| (&chunk.data, &chunk.light))
+    }
+
+    /// Chunks in view will be loaded and lit eventually, and pass their light down then.
+    fn is_open_sky(&self, chunk_pos: IVec3) -> bool {
+        !self.chunks.contains_key(&chunk_pos) && !self.is_visible_chunk(chunk_pos)
+    }
+
+    fn get_light_mut(&mut self, chunk_pos: IVec3) -> Option<&mut ChunkLight> {
+        self.chunks
+            .get_mut(&chunk_pos)
+            .filter(|chunk| chunk.lit)
+            .map(|chunk| &mut chunk.light)
+    }
+}
+
 fn regenerate_meshes(
     commands: &mut Commands,
     world: &mut World,
     meshes: &mut Assets<Mesh>,
+    texture_array: &BlockTextureArray,
     registry: Arc<Registry>,
+    chunk_messages: &mut ChunkMessages,
 ) {
     let task_pool = AsyncComputeTaskPool::get();
 
@@ -330,28 +1247,50 @@ fn regenerate_meshes(
     chunks_to_mesh.sort_by_key(|&chunk_pos| world.chunk_sort_key(chunk_pos));
 
     for chunk_pos in chunks_to_mesh {
-        if world.mesh_tasks.len() >= 16 {
+        if !world.mesh_budget.has_room(world.mesh_tasks.len()) {
             break;
         }
 
-        // If there are any unloaded neighbors, we shouldn't waste time generating a mesh for this chunk yet
-        let mut should_mesh = true;
+        // Empty chunks never have a mesh, whatever their neighbors are
+        if world.chunks[&chunk_pos].data.is_empty() {
+            hide_chunk(commands, world, chunk_pos, chunk_messages);
+            continue;
+        }
+
+        // If its light is still spreading or there are any unloaded or unlit neighbors, we shouldn't
+        // waste time generating a mesh for this chunk yet
+        let mut should_mesh =
+            world.chunks[&chunk_pos].lit && !world.relit_chunks.contains(&chunk_pos);
 
         for neighbor_pos in get_neighbors(chunk_pos) {
-            should_mesh &=
-                world.chunks.contains_key(&neighbor_pos) || !world.is_visible_chunk(neighbor_pos);
+            should_mesh &= match world.chunks.get(&neighbor_pos) {
+                Some(neighbor) => neighbor.lit,
+                None => !world.is_visible_chunk(neighbor_pos),
+            };
         }
 
         if !should_mesh {
             continue;
         }
 
+        if is_enclosed_chunk(world, chunk_pos, &registry) {
+            hide_chunk(commands, world, chunk_pos, chunk_messages);
+            continue;
+        }
+
         // Obtain a reference to neighboring chunks, since we need to generate the mesh for this chunk based on them
         let relevant_chunks = RelevantChunks::from_world(world, chunk_pos);
         let registry = registry.clone();
+        let greedy_meshing = world.greedy_meshing;
 
-        let task =
-            task_pool.spawn(async move { generate_mesh(chunk_pos, &relevant_chunks, &registry) });
+        let task = task_pool.spawn(async move {
+            generate_mesh(
+                chunk_pos,
+                &relevant_chunks.downsampled(),
+                &registry,
+                greedy_meshing,
+            )
+        });
 
         world.mesh_tasks.insert(chunk_pos, task);
     }
@@ -370,20 +1309,168 @@ fn regenerate_meshes(
             None => true,
         });
 
-    for (chunk_pos, mesh) in results {
+    for (chunk_pos, chunk_meshes) in results {
+        if chunk_meshes.is_empty() {
+            hide_chunk(commands, world, chunk_pos, chunk_messages);
+            continue;
+        }
+
         if let Some(chunk) = world.chunks.get_mut(&chunk_pos) {
-            if let Some(mesh) = mesh {
+            let entity = *chunk.entity.get_or_insert_with(|| {
                 commands
-                    .entity(chunk.entity)
-                    .insert((Visibility::Visible, Mesh3d(meshes.add(mesh))));
+                    .spawn(Transform::from_translation(
+                        chunk_pos.as_vec3() * CHUNK_SIZE as f32,
+                    ))
+                    .id()
+            });
+
+            commands.entity(entity).insert(Visibility::Visible);
+
+            if let Some(mesh) = chunk_meshes.opaque {
+                commands.entity(entity).insert((
+                    Mesh3d(meshes.add(mesh)),
+                    MeshMaterial3d(texture_array.material.clone()),
+                ));
             } else {
-                commands.entity(chunk.entity).insert(Visibility::Hidden);
+                commands
+                    .entity(entity)
+                    .remove::<(Mesh3d, MeshMaterial3d<ChunkMaterial>)>();
+            }
+
+            // Transparent faces are a separate mesh, so they can be blended and sorted on their own
+            match (chunk_meshes.transparent, chunk.transparent_entity) {
+                (Some((mesh, quads)), Some(transparent_entity)) => {
+                    commands
+                        .entity(transparent_entity)
+                        .insert((Mesh3d(meshes.add(mesh)), quads));
+                }
+                (Some((mesh, quads)), None) => {
+                    chunk.transparent_entity = Some(
+                        commands
+                            .spawn((
+                                ChildOf(entity),
+                                Transform::default(),
+                                Mesh3d(meshes.add(mesh)),
+                                MeshMaterial3d(texture_array.transparent_material.clone()),
+                                quads,
+                            ))
+                            .id(),
+                    );
+                }
+                (None, Some(transparent_entity)) => {
+                    commands.entity(transparent_entity).despawn();
+                    chunk.transparent_entity = None;
+                }
+                (None, None) => {}
             }
 
             // Mark the chunk as complete so we don't mesh it again
             chunk.dirty = false;
+
+            chunk_messages.meshed.write(ChunkMeshed {
+                chunk_pos,
+                entity: chunk.entity,
+            });
+        }
+    }
+}
+
+/// Transparent faces further away than this are only sorted when their chunk is meshed, since
+/// their order rarely changes as the camera moves.
+const SORT_DISTANCE: f32 = 4.0 * CHUNK_SIZE as f32;
+
+/// Sorts transparent faces back to front whenever the camera moves to another block, so they
+/// blend in the right order.
+fn sort_transparent_quads(
+    camera: Query<&GlobalTransform, With<PlayerCamera>>,
+    chunks: Query<(Ref<TransparentQuads>, &Mesh3d, &GlobalTransform)>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut last_camera_block: Local<Option<IVec3>>,
+) {
+    let Ok(camera) = camera.single() else {
+        return;
+    };
+
+    let camera_pos = camera.translation();
+    let camera_block = camera_pos.floor().as_ivec3();
+    let camera_moved = last_camera_block.replace(camera_block) != Some(camera_block);
+
+    for (quads, mesh, transform) in &chunks {
+        let view_pos = camera_pos - transform.translation();
+
+        let should_sort =
+            quads.is_changed() || (camera_moved && view_pos.length() <= SORT_DISTANCE);
+
+        if !should_sort {
+            continue;
+        }
+
+        if let Some(mesh) = meshes.get_mut(&mesh.0) {
+            mesh.insert_indices(Indices::U32(quads.sorted_indices(view_pos)));
+        }
+    }
+}
+
+/// Marks a chunk as meshed without giving it a mesh.
+fn hide_chunk(
+    commands: &mut Commands,
+    world: &mut World,
+    chunk_pos: IVec3,
+    chunk_messages: &mut ChunkMessages,
+) {
+    if let Some(chunk) = world.chunks.get_mut(&chunk_pos) {
+        if let Some(entity) = chunk.entity {
+            commands.entity(entity).insert(Visibility::Hidden);
+        }
+
+        chunk.dirty = false;
+
+        chunk_messages.meshed.write(ChunkMeshed {
+            chunk_pos,
+            entity: None,
+        });
+    }
+}
+
+/// Whether a chunk is solid and surrounded by solid chunks, so none of its faces can be seen.
+fn is_enclosed_chunk(world: &World, chunk_pos: IVec3, registry: &Registry) -> bool {
+    let is_solid = |chunk_pos: IVec3| {
+        world.chunks.get(&chunk_pos).is_some_and(|chunk| {
+            chunk.data.is_uniform()
+                && chunk
+                    .data
+                    .get_block(USizeVec3::ZERO)
+                    .is_some_and(|block| registry.block_type(block.id).is_opaque_cube(block.data))
+        })
+    };
+
+    is_solid(chunk_pos)
+        && BlockFace::ALL
+            .into_iter()
+            .all(|face| is_solid(chunk_pos + face.normal()))
+}
+
+/// Returns the offsets of the chunks whose meshes depend on the block at `local_pos`: its own
+/// chunk, plus the neighbors it touches through a face, edge or corner of the chunk. Meshes at
+/// `scale` sample whole downsampled blocks, so blocks within `scale` of the border count.
+pub(crate) fn border_neighbors(local_pos: USizeVec3, scale: usize) -> Vec<IVec3> {
+    let offsets = |local: usize| match local {
+        local if local < scale => -1..=0,
+        local if local >= CHUNK_SIZE - scale => 0..=1,
+        _ => 0..=0,
+    };
+
+    let mut neighbors = Vec::new();
+
+    for x in offsets(local_pos.x) {
+        for y in offsets(local_pos.y) {
+            for z in offsets(local_pos.z) {
+                neighbors.push(IVec3::new(x, y, z));
+            }
         }
     }
+
+    neighbors
 }
 
 fn get_neighbors(chunk_pos: IVec3) -> Vec<IVec3> {
@@ -404,11 +1491,161 @@ fn get_neighbors(chunk_pos: IVec3) -> Vec<IVec3> {
     neighbors
 }
 
-fn debug_ui(mut contexts: EguiContexts, mut world: ResMut<World>) -> Result {
+fn send_block_changes(mut world: ResMut<World>, mut messages: MessageWriter<BlockChanged>) {
+    // Only reading doesn't flag the world as changed, which draining every frame would
+    if world.block_changes.is_empty() {
+        return;
+    }
+
+    messages.write_batch(world.block_changes.drain(..));
+}
+
+/// The periodic save runs in the background, so anything edited since then would be lost when
+/// the app closes without this.
+fn save_on_exit(mut world: ResMut<World>, mut exits: MessageReader<AppExit>) {
+    if exits.is_empty() {
+        return;
+    }
+
+    exits.clear();
+
+    match world.save_now() {
+        Ok(()) => log::info!("Saved world {}", world.world_name),
+        Err(error) => log::error!("Failed to save world {} on exit: {error}", world.world_name),
+    }
+}
+
+struct WorldsUi {
+    /// Cached so the save directory isn't read every frame.
+    worlds: Option<Vec<String>>,
+    new_world_name: String,
+    new_world_seed: u32,
+}
+
+impl Default for WorldsUi {
+    fn default() -> Self {
+        Self {
+            worlds: None,
+            new_world_name: String::new(),
+            new_world_seed: DEFAULT_SEED,
+        }
+    }
+}
+
+fn debug_ui(
+    mut contexts: EguiContexts,
+    mut world: ResMut<World>,
+    mut worlds_ui: Local<WorldsUi>,
+) -> Result {
     egui::Window::new("Settings")
         .anchor(egui::Align2::RIGHT_TOP, egui::Vec2::new(-10.0, 10.0))
         .show(contexts.ctx_mut()?, |ui| {
-            ui.add(Slider::new(&mut world.generation_radius, 1..=32).text("Generation Radius"));
+            let mut view_distance = world.view_distance;
+
+            ui.add(Slider::new(&mut view_distance.horizontal, 1..=64).text("Horizontal Distance"));
+            ui.add(Slider::new(&mut view_distance.vertical, 1..=32).text("Vertical Distance"));
+
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut view_distance.shape, LoadShape::Cylinder, "Cylinder");
+                ui.radio_value(&mut view_distance.shape, LoadShape::Sphere, "Sphere");
+            });
+
+            egui::CollapsingHeader::new("LOD Distances").show(ui, |ui| {
+                for (level, distance) in view_distance.lod_distances.iter_mut().enumerate() {
+                    ui.add(Slider::new(distance, 1..=64).text(format!("{}x", 2 << level)));
+                }
+            });
+
+            world.set_view_distance(view_distance);
+
+            let mut greedy_meshing = world.greedy_meshing;
+            ui.checkbox(&mut greedy_meshing, "Greedy Meshing");
+            world.set_greedy_meshing(greedy_meshing);
+
+            egui::CollapsingHeader::new("Metrics").show(ui, |ui| {
+                let metrics = world.metrics();
+
+                ui.label(format!("Loaded chunks: {}", metrics.loaded_chunks));
+                ui.label(format!(
+                    "Generation tasks: {}/{}",
+                    metrics.generation_tasks, metrics.generation_budget
+                ));
+                ui.label(format!(
+                    "Mesh tasks: {}/{}",
+                    metrics.mesh_tasks, metrics.mesh_budget
+                ));
+                ui.label(format!("Chunks to light: {}", metrics.chunks_to_light));
+                ui.label(format!("Chunks to mesh: {}", metrics.chunks_to_mesh));
+                ui.label(format!("Chunks to save: {}", metrics.chunks_to_save));
+
+                if metrics.save_failures > 0 {
+                    ui.colored_label(
+                        egui::Color32::RED,
+                        format!("Saving failed {} times, see the log", metrics.save_failures),
+                    );
+                }
+                ui.label(format!("Loaded regions: {}", metrics.loaded_regions));
+            });
+
+            if ui.button("Save Now").clicked()
+                && let Err(error) = world.save_now()
+            {
+                log::error!("Failed to save world {}: {error}", world.world_name);
+            }
+
+            egui::CollapsingHeader::new(format!("World: {}", world.world_name)).show(ui, |ui| {
+                let worlds = worlds_ui.worlds.get_or_insert_with(|| {
+                    world.list_worlds().unwrap_or_else(|error| {
+                        log::error!("Failed to list worlds: {error}");
+                        Vec::new()
+                    })
+                });
+
+                let mut refresh = false;
+
+                for name in worlds.iter() {
+                    ui.horizontal(|ui| {
+                        ui.label(name);
+
+                        if *name == world.world_name {
+                            return;
+                        }
+
+                        if ui.button("Open").clicked() {
+                            world.switch_world(name);
+                        }
+
+                        if ui.button("Delete").clicked() {
+                            if let Err(error) = world.delete_world(name) {
+                                log::error!("Failed to delete world {name}: {error}");
+                            }
+
+                            refresh = true;
+                        }
+                    });
+                }
+
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(&mut worlds_ui.new_world_name);
+                    ui.add(DragValue::new(&mut worlds_ui.new_world_seed).prefix("Seed: "));
+
+                    if ui.button("Create").clicked() {
+                        let name = mem::take(&mut worlds_ui.new_world_name);
+
+                        if let Err(error) = world.create_world(&name, worlds_ui.new_world_seed) {
+                            log::error!("Failed to create world {name}: {error}");
+                        }
+
+                        refresh = true;
+                    }
+                });
+
+                if refresh || ui.button("Refresh").clicked() {
+                    worlds_ui.worlds = None;
+                }
+            });
         });
     Ok(())
 }
@@ -416,6 +1653,8 @@ fn debug_ui(mut contexts: EguiContexts, mut world: ResMut<World>) -> Result {
 struct GenerationResult {
     chunk_data: ChunkData,
     needs_saving: bool,
+    /// The chunk couldn't be read, so `chunk_data` is a generated stand-in.
+    load_failed: bool,
 }
 
 fn generate_chunk(
@@ -424,39 +1663,375 @@ fn generate_chunk(
     chunk_pos: IVec3,
     registry: &Registry,
 ) -> GenerationResult {
-    if let Some(chunk_data) = region_manager.load_chunk(chunk_pos) {
-        return GenerationResult {
-            chunk_data,
-            needs_saving: false,
-        };
-    }
+    let load_failed = match region_manager.load_chunk(chunk_pos) {
+        Ok(Some(chunk_data)) => {
+            return GenerationResult {
+                chunk_data,
+                needs_saving: false,
+                load_failed: false,
+            };
+        }
+        Ok(None) => false,
+        Err(error) => {
+            // Don't overwrite whatever is on disk, since it may be readable next time
+            log::error!("Failed to load chunk {chunk_pos}, generating a stand-in: {error}");
+            true
+        }
+    };
 
     let chunk_data = world_generator.generate_chunk(chunk_pos, registry);
 
     GenerationResult {
         chunk_data,
-        needs_saving: true,
+        needs_saving: !load_failed,
+        load_failed,
     }
 }
 
-fn save_chunks(mut world: ResMut<World>) {
-    if world.chunks_to_save.is_empty() || world.last_save_time.elapsed() < Duration::from_secs(3) {
+/// Failed loads are retried after this long, doubling with every failure up to
+/// `MAX_LOAD_RETRY_DELAY`.
+const LOAD_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_LOAD_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+struct LoadRetry {
+    failures: u32,
+    retry_at: Instant,
+    task: Option<Task<Result<Option<ChunkData>, RegionError>>>,
+}
+
+impl LoadRetry {
+    fn after_failures(failures: u32) -> Self {
+        let delay = LOAD_RETRY_DELAY.saturating_mul(1 << (failures - 1).min(16));
+
+        Self {
+            failures,
+            retry_at: Instant::now() + delay.min(MAX_LOAD_RETRY_DELAY),
+            task: None,
+        }
+    }
+}
+
+/// Tries loading chunks that failed to load again, replacing their read-only stand-ins once the
+/// load succeeds.
+fn retry_failed_loads(world: &mut World) {
+    let task_pool = AsyncComputeTaskPool::get();
+    let mut results = Vec::new();
+
+    for (chunk_pos, retry) in world.load_retries.iter_mut() {
+        let chunk_pos = *chunk_pos;
+
+        match &mut retry.task {
+            Some(task) => {
+                if let Some(result) = check_ready(task) {
+                    results.push((chunk_pos, result));
+                }
+            }
+            None if retry.retry_at <= Instant::now() => {
+                let region_manager = world.region_manager.clone();
+                retry.task =
+                    Some(task_pool.spawn(async move { region_manager.load_chunk(chunk_pos) }));
+            }
+            None => {}
+        }
+    }
+
+    for (chunk_pos, result) in results {
+        let failures = world.load_retries[&chunk_pos].failures;
+
+        let chunk_data = match result {
+            Ok(chunk_data) => chunk_data,
+            Err(error) => {
+                let retry = LoadRetry::after_failures(failures + 1);
+                let delay = retry.retry_at - Instant::now();
+                log::warn!("Failed to load chunk {chunk_pos}, retrying in {delay:.0?}: {error}");
+                world.load_retries.insert(chunk_pos, retry);
+                continue;
+            }
+        };
+
+        world.load_retries.swap_remove(&chunk_pos);
+        log::info!("Loaded chunk {chunk_pos} after {failures} failed attempts");
+
+        let Some(chunk) = world.chunks.get_mut(&chunk_pos) else {
+            continue;
+        };
+
+        chunk.read_only = false;
+
+        match chunk_data {
+            Some(chunk_data) => world.replace_chunk_data(chunk_pos, chunk_data),
+            // There was nothing on disk after all, so the stand-in is saved like any new chunk
+            None => {
+                let chunk_data = chunk.data.clone();
+                world.chunks_to_save.insert(chunk_pos, chunk_data);
+            }
+        }
+    }
+}
+
+/// Returns the directory of a world, making sure the name can't point outside of the save path.
+fn world_dir(save_path: &Path, name: &str) -> Result<PathBuf, RegionError> {
+    let is_valid = !name.is_empty()
+        && name != "."
+        && name != ".."
+        && !name.contains(['/', '\\'])
+        && !name.starts_with('.');
+
+    if !is_valid {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid world name {name:?}"),
+        )
+        .into());
+    }
+
+    Ok(save_path.join(name))
+}
+
+/// Moves a world saved in `legacy_dir` to `world_dir`, unless there's already a world there.
+/// Returns whether it was moved.
+fn adopt_legacy_world(legacy_dir: &Path, world_dir: &Path) -> Result<bool, RegionError> {
+    if world_dir.try_exists()? || !legacy_dir.is_dir() {
+        return Ok(false);
+    }
+
+    if let Some(parent) = world_dir.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(legacy_dir, world_dir)?;
+
+    Ok(true)
+}
+
+/// Opens the world in `dir`, creating it with `seed` if it doesn't exist yet.
+fn open_world(
+    dir: &Path,
+    seed: u32,
+    codec: Codec,
+) -> Result<(RegionManager, WorldMetadata), RegionError> {
+    let region_manager = RegionManager::with_codec(dir.to_path_buf(), codec)?;
+    let metadata = WorldMetadata::load_or_create(dir, || {
+        WorldMetadata::new(seed, GeneratorSettings::default())
+    })?;
+
+    Ok((region_manager, metadata))
+}
+
+/// Matches the world's saved block and material ids up with the registry, which has to happen
+/// before any chunks are loaded or saved.
+fn setup_id_map(mut world: ResMut<World>, shared_registry: Res<SharedRegistry>) {
+    install_id_map(&mut world, &shared_registry.0);
+}
+
+fn install_id_map(world: &mut World, registry: &Arc<Registry>) {
+    let world_dir = world.save_path.join(&world.world_name);
+    let metadata = &mut world.metadata;
+    let table_sizes = (metadata.block_names.len(), metadata.material_names.len());
+
+    let id_map = IdMap::new(
+        registry.clone(),
+        &mut metadata.block_names,
+        &mut metadata.material_names,
+    );
+
+    if table_sizes != (metadata.block_names.len(), metadata.material_names.len())
+        && let Err(error) = metadata.save(&world_dir)
+    {
+        log::error!("Failed to save the world metadata: {error}");
+    }
+
+    world.region_manager.set_id_map(id_map);
+}
+
+fn switch_world(
+    mut commands: Commands,
+    mut world: ResMut<World>,
+    shared_registry: Res<SharedRegistry>,
+    mut chunk_messages: ChunkMessages,
+) {
+    let Some(name) = world.world_to_open.take() else {
+        return;
+    };
+
+    let dir = match world_dir(&world.save_path, &name) {
+        Ok(dir) if WorldMetadata::exists(&dir) => dir,
+        Ok(_) => {
+            log::error!("Failed to open world {name}: it doesn't exist");
+            return;
+        }
+        Err(error) => {
+            log::error!("Failed to open world {name}: {error}");
+            return;
+        }
+    };
+
+    // Everything pending has to reach the old world before its region manager is dropped
+    if let Err(error) = world.save_now() {
+        log::error!(
+            "Failed to save world {}, staying in it: {error}",
+            world.world_name
+        );
+        return;
+    }
+
+    let (region_manager, metadata) = match open_world(&dir, DEFAULT_SEED, world.codec) {
+        Ok(world) => world,
+        Err(error) => {
+            log::error!("Failed to open world {name}: {error}");
+            return;
+        }
+    };
+
+    for (chunk_pos, chunk) in world.chunks.drain(..) {
+        if let Some(entity) = chunk.entity {
+            commands.entity(entity).despawn();
+        }
+
+        chunk_messages.unloaded.write(ChunkUnloaded { chunk_pos });
+    }
+
+    world.generation_tasks.clear();
+    world.mesh_tasks.clear();
+    world.load_retries.clear();
+    world.chunks_to_light.clear();
+    world.blocks_to_relight.clear();
+    world.light_queue = LightQueue::default();
+    world.relit_chunks.clear();
+    world.reset_loading();
+
+    world.region_manager = Arc::new(region_manager);
+    world.generator = WorldGenerator::with_settings(metadata.seed, metadata.generator);
+    world.metadata = metadata;
+    world.world_name = name;
+
+    install_id_map(&mut world, &shared_registry.0);
+
+    log::info!("Switched to world {}", world.world_name);
+}
+
+/// Regions only hold the offset tables and file handles, so this is a few megabytes at most.
+const MAX_LOADED_REGIONS: usize = 64;
+
+fn evict_regions(mut world: ResMut<World>) {
+    if world.last_eviction_time.elapsed() < Duration::from_secs(5) {
         return;
     }
 
-    if let Some(task) = world.save_task.as_mut()
-        && check_ready(task).is_none()
+    world.region_manager.evict_regions(
+        world.center_pos,
+        world.view_distance.max_radius(),
+        MAX_LOADED_REGIONS,
+    );
+
+    world.last_eviction_time = Instant::now();
+}
+
+const SAVE_INTERVAL: Duration = Duration::from_secs(3);
+
+/// Periodic saves stop after this many failures in a row, leaving the chunks queued for
+/// `World::save_now`.
+const MAX_SAVE_FAILURES: u32 = 5;
+
+fn save_chunks(mut world: ResMut<World>) {
+    if let Some(task) = world.save_task.as_mut() {
+        let Some(result) = check_ready(task) else {
+            return;
+        };
+
+        world.save_task = None;
+
+        match world.finish_save(result) {
+            Ok(()) => world.save_failures = 0,
+            Err(error) => {
+                world.save_failures += 1;
+
+                if world.save_failures < MAX_SAVE_FAILURES {
+                    log::error!("Failed to save chunks, retrying later: {error}");
+                } else {
+                    log::error!(
+                        "Failed to save chunks {MAX_SAVE_FAILURES} times in a row, pausing saves \
+                         until the world is saved manually: {error}"
+                    );
+                }
+            }
+        }
+    }
+
+    // Each failure doubles the wait before the next attempt
+    let interval = SAVE_INTERVAL * (1 << world.save_failures.min(MAX_SAVE_FAILURES));
+
+    if world.chunks_to_save.is_empty()
+        || world.save_failures >= MAX_SAVE_FAILURES
+        || world.last_save_time.elapsed() < interval
     {
         return;
     }
 
     let task_pool = AsyncComputeTaskPool::get();
     let region_manager = world.region_manager.clone();
-    let chunks = mem::take(&mut world.chunks_to_save);
+    let chunks = Arc::new(mem::take(&mut world.chunks_to_save));
+    world.saving_chunks = chunks.clone();
 
-    world.save_task = Some(task_pool.spawn(async move {
-        region_manager.save_chunks(&chunks);
-    }));
+    world.save_task = Some(task_pool.spawn(async move { region_manager.save_chunks(&chunks) }));
 
     world.last_save_time = Instant::now();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BlockId, ChunkDataInner, PackedData};
+
+    #[test]
+    fn test_border_neighbors() {
+        let last = CHUNK_SIZE - 1;
+
+        // Interior blocks only affect their own chunk
+        assert_eq!(
+            border_neighbors(USizeVec3::new(5, 6, 7), 1),
+            vec![IVec3::ZERO]
+        );
+
+        // Faces affect one neighbor, edges three and corners seven
+        assert_eq!(border_neighbors(USizeVec3::new(0, 6, 7), 1).len(), 2);
+        assert_eq!(border_neighbors(USizeVec3::new(0, last, 7), 1).len(), 4);
+        assert_eq!(border_neighbors(USizeVec3::new(0, last, 0), 1).len(), 8);
+        assert!(border_neighbors(USizeVec3::new(0, last, 0), 1).contains(&IVec3::new(-1, 1, -1)));
+
+        // Downsampled meshes see blocks further from the border as part of the border blocks
+        assert_eq!(border_neighbors(USizeVec3::new(3, 6, 7), 1).len(), 1);
+        assert_eq!(border_neighbors(USizeVec3::new(3, 6, 7), 4).len(), 2);
+        assert_eq!(border_neighbors(USizeVec3::new(3, last - 3, 7), 4).len(), 4);
+        assert_eq!(border_neighbors(USizeVec3::new(3, last - 4, 7), 4).len(), 2);
+    }
+
+    #[test]
+    fn test_adopt_legacy_world() {
+        let dir = std::env::temp_dir().join(format!("voxel-legacy-world-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let legacy_dir = dir.join("regions");
+        let world_dir = dir.join("saves").join("world");
+
+        // Before there were named worlds, the region files were saved straight into one folder
+        let block = Block::new(BlockId(1), PackedData::builder().build());
+        let chunks = HashMap::from([(IVec3::ONE, Arc::new(ChunkDataInner::filled(Some(block))))]);
+        RegionManager::new(legacy_dir.clone())
+            .unwrap()
+            .save_chunks(&chunks)
+            .unwrap();
+
+        assert!(adopt_legacy_world(&legacy_dir, &world_dir).unwrap());
+        assert!(!legacy_dir.exists());
+
+        let (region_manager, _) = open_world(&world_dir, DEFAULT_SEED, Codec::default()).unwrap();
+        let chunk = region_manager.load_chunk(IVec3::ONE).unwrap().unwrap();
+        assert_eq!(chunk.get_block(USizeVec3::ZERO), Some(block));
+
+        // An existing world is never replaced
+        fs::create_dir_all(&legacy_dir).unwrap();
+        assert!(!adopt_legacy_world(&legacy_dir, &world_dir).unwrap());
+        assert!(legacy_dir.exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
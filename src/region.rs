@@ -1,82 +1,307 @@
 use std::{
-    collections::{HashMap, HashSet},
-    fs,
-    path::PathBuf,
-    sync::Arc,
+    collections::HashMap,
+    error::Error,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 use bevy::math::IVec3;
 use indexmap::IndexMap;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use serde::{Deserialize, Serialize};
 
-use crate::{Block, ChunkData, ChunkDataInner};
+use crate::{Block, CHUNK_SIZE, ChunkData, ChunkDataInner, Codec, IdMap};
 
 const REGION_SIZE: usize = 16;
+const REGION_MAGIC: [u8; 4] = *b"VXRS";
+const REGION_VERSION: u32 = REGION_MIGRATIONS.len() as u32;
+const CHUNKS_PER_REGION: usize = REGION_SIZE * REGION_SIZE * REGION_SIZE;
+const SECTOR_SIZE: usize = 1024;
+const ENTRY_SIZE: usize = 16;
+const HEADER_SECTORS: u32 = 1 + (CHUNKS_PER_REGION * ENTRY_SIZE).div_ceil(SECTOR_SIZE) as u32;
+
+const LEGACY_REGION_MAGIC: [u8; 4] = *b"VXRG";
+const LEGACY_REGION_HEADER_SIZE: usize = 16;
+
+/// Upgrades the contents of a region file by one version, returning `None` if it's corrupt.
+type RegionMigration = fn(&[u8], Codec) -> Result<Option<Vec<u8>>, RegionError>;
+
+/// Region files written by older builds are upgraded one version at a time when they're opened,
+/// so the migration at index `n` upgrades version `n` to `n + 1`. Version 0 is the original
+/// format, where the whole region was a single postcard blob.
+///
+/// The version also covers the chunks stored in the file, down to how `Block` and `PackedData`
+/// are serialized, so changing their layout needs a migration as well.
+const REGION_MIGRATIONS: &[RegionMigration] = &[migrate_blob_to_sectors];
 
 pub struct RegionManager {
     region_dir: PathBuf,
-    loaded_regions: RwLock<HashMap<IVec3, Region>>,
+    codec: Codec,
+    loaded_regions: RwLock<HashMap<IVec3, LoadedRegion>>,
+    /// Held by the task opening a region, so it's only opened once without blocking access to
+    /// the other regions while its file is read or migrated.
+    opening_regions: Mutex<HashMap<IVec3, Arc<Mutex<()>>>>,
+    access_counter: AtomicU64,
+    id_map: RwLock<Option<Arc<IdMap>>>,
+}
+
+struct LoadedRegion {
+    file: Arc<Mutex<RegionFile>>,
+    last_used: AtomicU64,
 }
 
 impl RegionManager {
-    pub fn new(region_dir: PathBuf) -> Self {
-        if !region_dir.try_exists().unwrap() {
-            fs::create_dir_all(&region_dir).unwrap();
+    pub fn new(region_dir: PathBuf) -> Result<Self, RegionError> {
+        Self::with_codec(region_dir, Codec::default())
+    }
+
+    /// The codec is only used for new region files, existing ones keep the codec they were
+    /// created with.
+    pub fn with_codec(region_dir: PathBuf, codec: Codec) -> Result<Self, RegionError> {
+        if !codec.is_supported() {
+            return Err(RegionError::UnsupportedCodec(codec.tag()));
         }
 
-        Self {
+        fs::create_dir_all(&region_dir)?;
+
+        Ok(Self {
             region_dir,
+            codec,
             loaded_regions: RwLock::new(HashMap::new()),
+            opening_regions: Mutex::new(HashMap::new()),
+            access_counter: AtomicU64::new(0),
+            id_map: RwLock::new(None),
+        })
+    }
+
+    /// Sets the mapping between registry ids and the ids stored in the world's region files.
+    /// Without one, blocks are stored with their registry ids.
+    pub fn set_id_map(&self, id_map: IdMap) {
+        *self.id_map.write() = (!id_map.is_identity()).then(|| Arc::new(id_map));
+    }
+
+    pub fn load_chunk(&self, chunk_pos: IVec3) -> Result<Option<ChunkData>, RegionError> {
+        let Some(region) = self.region(self.region_pos(chunk_pos), false)? else {
+            return Ok(None);
+        };
+
+        let mut region = region.lock();
+        let index = chunk_index(chunk_pos);
+
+        let chunk_data = match region.read_chunk(index) {
+            Ok(Some(mut chunk)) => {
+                if let Some(id_map) = self.id_map.read().as_deref() {
+                    chunk.map_blocks(|block| id_map.load_block(block));
+                }
+
+                chunk.decompress()
+            }
+            Ok(None) => return Ok(None),
+            Err(RegionError::CorruptChunk(_)) => None,
+            Err(error) => return Err(error),
+        };
+
+        if chunk_data.is_none() {
+            // The chunk will be regenerated and written to fresh sectors
+            log::warn!("Chunk {chunk_pos} is corrupt, quarantining it");
+            let path = self.file_path(self.region_pos(chunk_pos));
+            region.quarantine_chunk(index, &path)?;
         }
+
+        Ok(chunk_data)
     }
 
-    pub fn load_chunk(&self, chunk_pos: IVec3) -> Option<ChunkData> {
-        let region_pos = self.region_pos(chunk_pos);
+    pub fn save_chunks(&self, chunks: &HashMap<IVec3, ChunkData>) -> Result<(), RegionError> {
+        let mut regions: HashMap<IVec3, Vec<(usize, &ChunkData)>> = HashMap::new();
 
-        if let Some(region) = self.loaded_regions.read().get(&region_pos) {
-            return Some(region.chunks.get(&chunk_pos)?.decompress());
+        for (chunk_pos, chunk_data) in chunks {
+            regions
+                .entry(self.region_pos(*chunk_pos))
+                .or_default()
+                .push((chunk_index(*chunk_pos), chunk_data));
         }
 
-        let mut loaded_regions = self.loaded_regions.write();
+        let id_map = self.id_map.read().clone();
 
-        let path = self.file_path(region_pos);
+        for (region_pos, chunks) in regions {
+            let region = self
+                .region(region_pos, true)?
+                .expect("region should have been created");
 
-        if !path.exists() {
-            return None;
+            // Compress outside of the lock, so loads from this region aren't blocked
+            let codec = region.lock().codec;
+            let mut encoded_chunks = Vec::with_capacity(chunks.len());
+
+            for (index, chunk_data) in chunks {
+                let mut chunk = CompressedChunk::compress(chunk_data);
+
+                if let Some(id_map) = &id_map {
+                    chunk.map_blocks(|block| Some(id_map.save_block(block)));
+                }
+
+                let chunk = postcard::to_allocvec(&chunk)?;
+                encoded_chunks.push((index, codec.compress(&chunk)?));
+            }
+
+            region.lock().write_chunks(encoded_chunks)?;
         }
 
-        let data = fs::read(path).unwrap();
-        let region: Region = postcard::from_bytes(&data).unwrap();
+        Ok(())
+    }
 
-        let chunk_data = region
-            .chunks
-            .get(&chunk_pos)
-            .map(CompressedChunk::decompress);
+    /// Syncs every open region file to disk.
+    pub fn flush(&self) -> Result<(), RegionError> {
+        for region in self.loaded_regions.read().values() {
+            region.file.lock().flush()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn loaded_region_count(&self) -> usize {
+        self.loaded_regions.read().len()
+    }
+
+    /// Closes region files that are more than `radius` chunks away from `center_pos`, and then
+    /// the least recently used ones until at most `max_regions` are open. Regions that are in use
+    /// by another task are left alone, and dirty regions are flushed before being closed.
+    pub fn evict_regions(&self, center_pos: IVec3, radius: i32, max_regions: usize) {
+        let evicted_regions = self.take_evicted_regions(center_pos, radius, max_regions);
 
-        loaded_regions.insert(region_pos, region);
+        // Flushing happens after releasing the lock, so loading other regions isn't blocked
+        for (region_pos, region) in evicted_regions {
+            let result = region.file.lock().flush();
 
-        chunk_data
+            if let Err(error) = result {
+                log::error!("Failed to flush region {region_pos}, keeping it loaded: {error}");
+                self.loaded_regions
+                    .write()
+                    .entry(region_pos)
+                    .or_insert(region);
+            }
+        }
     }
 
-    pub fn save_chunks(&self, chunks: &HashMap<IVec3, ChunkData>) {
+    /// Removes the regions that `evict_regions` closes from the loaded regions.
+    fn take_evicted_regions(
+        &self,
+        center_pos: IVec3,
+        radius: i32,
+        max_regions: usize,
+    ) -> Vec<(IVec3, LoadedRegion)> {
         let mut loaded_regions = self.loaded_regions.write();
-        let mut modified_regions = HashSet::new();
+        let mut evicted_regions = Vec::new();
 
-        for (chunk_pos, chunk_data) in chunks {
-            let region_pos = self.region_pos(*chunk_pos);
-            let region = loaded_regions.entry(region_pos).or_default();
-            region
-                .chunks
-                .insert(*chunk_pos, CompressedChunk::compress(chunk_data));
-            modified_regions.insert(region_pos);
+        let center_region = self.region_pos(center_pos);
+        let region_radius = radius / REGION_SIZE as i32 + 1;
+
+        // Out of range regions come first, then the least recently used
+        let mut candidates = loaded_regions
+            .iter()
+            .filter(|(_, region)| Arc::strong_count(&region.file) == 1)
+            .map(|(region_pos, region)| {
+                let in_range = (*region_pos - center_region).abs().max_element() <= region_radius;
+                (
+                    in_range,
+                    region.last_used.load(Ordering::Relaxed),
+                    *region_pos,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        candidates.sort_by_key(|(in_range, last_used, _)| (*in_range, *last_used));
+
+        for (in_range, _, region_pos) in candidates {
+            if in_range && (loaded_regions.len() <= max_regions || region_pos == center_region) {
+                continue;
+            }
+
+            if let Some(region) = loaded_regions.remove(&region_pos) {
+                evicted_regions.push((region_pos, region));
+            }
+        }
+
+        evicted_regions
+    }
+
+    /// Returns the open region file, opening or creating it on disk if needed.
+    fn region(
+        &self,
+        region_pos: IVec3,
+        create: bool,
+    ) -> Result<Option<Arc<Mutex<RegionFile>>>, RegionError> {
+        if let Some(region) = self.loaded_regions.read().get(&region_pos) {
+            return Ok(Some(self.touch(region)));
         }
 
-        for region_pos in modified_regions {
-            let path = self.file_path(region_pos);
-            let region = loaded_regions.get(&region_pos).unwrap();
-            fs::write(path, postcard::to_allocvec(region).unwrap()).unwrap();
+        let opening = self
+            .opening_regions
+            .lock()
+            .entry(region_pos)
+            .or_default()
+            .clone();
+        let _opening = opening.lock();
+
+        // Another task may have opened the region while we were waiting for the lock
+        if let Some(region) = self.loaded_regions.read().get(&region_pos) {
+            return Ok(Some(self.touch(region)));
         }
+
+        let result = self.open_region(region_pos, create);
+        self.opening_regions.lock().remove(&region_pos);
+
+        result
+    }
+
+    /// Opens a region file from disk and adds it to the loaded regions. Only one task opens a
+    /// region at a time, see `opening_regions`.
+    fn open_region(
+        &self,
+        region_pos: IVec3,
+        create: bool,
+    ) -> Result<Option<Arc<Mutex<RegionFile>>>, RegionError> {
+        let path = self.file_path(region_pos);
+
+        if !path.try_exists()? {
+            if !create {
+                return Ok(None);
+            }
+
+            write_atomic(&path, &encode_region_file(self.codec, &[]))?;
+        }
+
+        let region = match RegionFile::open(&path, self.codec)? {
+            Some(region) => region,
+            None => {
+                log::warn!("Region file {} is corrupt, quarantining it", path.display());
+                quarantine(&path)?;
+                write_atomic(&path, &encode_region_file(self.codec, &[]))?;
+                RegionFile::open(&path, self.codec)?
+                    .expect("newly created region file should be valid")
+            }
+        };
+
+        let region = LoadedRegion {
+            file: Arc::new(Mutex::new(region)),
+            last_used: AtomicU64::new(0),
+        };
+        let file = self.touch(&region);
+        self.loaded_regions.write().insert(region_pos, region);
+
+        Ok(Some(file))
+    }
+
+    fn touch(&self, region: &LoadedRegion) -> Arc<Mutex<RegionFile>> {
+        let tick = self.access_counter.fetch_add(1, Ordering::Relaxed);
+        region.last_used.store(tick, Ordering::Relaxed);
+        region.file.clone()
     }
 
     fn file_path(&self, region_pos: IVec3) -> PathBuf {
@@ -91,11 +316,481 @@ impl RegionManager {
     }
 }
 
+#[derive(Debug)]
+pub enum RegionError {
+    Io(io::Error),
+    Serialization(postcard::Error),
+    CorruptChunk(IVec3),
+    UnsupportedCodec(u8),
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for RegionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "region I/O error: {error}"),
+            Self::Serialization(error) => write!(f, "region serialization error: {error}"),
+            Self::CorruptChunk(chunk_pos) => write!(f, "chunk {chunk_pos} is corrupt"),
+            Self::UnsupportedCodec(tag) => {
+                write!(
+                    f,
+                    "region uses codec {tag}, which isn't enabled in this build"
+                )
+            }
+            Self::UnsupportedVersion(version) => {
+                write!(
+                    f,
+                    "format version {version} is newer than this build supports"
+                )
+            }
+        }
+    }
+}
+
+impl Error for RegionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            Self::Serialization(error) => Some(error),
+            Self::CorruptChunk(_) | Self::UnsupportedCodec(_) | Self::UnsupportedVersion(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for RegionError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<postcard::Error> for RegionError {
+    fn from(error: postcard::Error) -> Self {
+        Self::Serialization(error)
+    }
+}
+
+/// A region file is split into fixed size sectors. The first sector holds the magic bytes, format
+/// version and the codec used to compress chunks, followed by a table with an entry for each
+/// chunk in the region. Each chunk is stored in a contiguous run of sectors, so it can be read
+/// and rewritten without touching the rest of the file.
+struct RegionFile {
+    file: File,
+    entries: Vec<ChunkEntry>,
+    used_sectors: Vec<bool>,
+    dirty: bool,
+    codec: Codec,
+}
+
+impl RegionFile {
+    /// Opens an existing region file, migrating it to the current version if it was written by
+    /// an older build. Migrated chunks are compressed with the given codec. Returns `None` if the
+    /// file is corrupt.
+    fn open(path: &Path, codec: Codec) -> Result<Option<Self>, RegionError> {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+
+        let mut header = vec![0; HEADER_SECTORS as usize * SECTOR_SIZE];
+
+        // Anything without the magic bytes predates versioning, and is treated as version 0
+        let version = if file.read_exact(&mut header).is_ok() && header.starts_with(&REGION_MAGIC) {
+            u32::from_le_bytes(header[4..8].try_into().unwrap())
+        } else {
+            0
+        };
+
+        if version > REGION_VERSION {
+            return Err(RegionError::UnsupportedVersion(version));
+        }
+
+        if version < REGION_VERSION {
+            drop(file);
+
+            let Some(data) = migrate_region(fs::read(path)?, version, codec)? else {
+                return Ok(None);
+            };
+
+            // Keep the original around in case the migration loses anything
+            let mut backup_path = path.as_os_str().to_owned();
+            backup_path.push(format!(".v{version}.bak"));
+            fs::copy(path, &backup_path)?;
+
+            log::info!(
+                "Migrated region file {} from version {version} to {REGION_VERSION}",
+                path.display()
+            );
+
+            write_atomic(path, &data)?;
+
+            return Self::open(path, codec);
+        }
+
+        let codec = Codec::from_tag(header[8]).ok_or(RegionError::UnsupportedCodec(header[8]))?;
+
+        let total_sectors = (file.metadata()?.len() as usize).div_ceil(SECTOR_SIZE);
+
+        let mut region = Self {
+            file,
+            entries: vec![ChunkEntry::default(); CHUNKS_PER_REGION],
+            used_sectors: vec![false; total_sectors],
+            dirty: false,
+            codec,
+        };
+
+        region.used_sectors[..HEADER_SECTORS as usize].fill(true);
+
+        let mut invalid_entries = Vec::new();
+
+        for (index, bytes) in header[SECTOR_SIZE..]
+            .chunks_exact(ENTRY_SIZE)
+            .take(CHUNKS_PER_REGION)
+            .enumerate()
+        {
+            let entry = ChunkEntry::from_bytes(bytes);
+
+            if !entry.is_present() {
+                continue;
+            }
+
+            let sectors = entry.sectors();
+
+            // Entries pointing outside of the file or overlapping others can't be trusted
+            if sectors.start < HEADER_SECTORS as usize
+                || sectors.end > total_sectors
+                || region.used_sectors[sectors.clone()].contains(&true)
+            {
+                log::warn!(
+                    "Ignoring invalid entry {index} in region file {}",
+                    path.display()
+                );
+                invalid_entries.push(index);
+                continue;
+            }
+
+            region.used_sectors[sectors].fill(true);
+            region.entries[index] = entry;
+        }
+
+        // Cleared on disk too, so they can't take over sectors once the file grows or other
+        // chunks are written there
+        if !invalid_entries.is_empty() {
+            for index in invalid_entries {
+                region.write_entry(index, ChunkEntry::default())?;
+            }
+
+            region.file.sync_data()?;
+        }
+
+        Ok(Some(region))
+    }
+
+    fn read_chunk(&mut self, index: usize) -> Result<Option<CompressedChunk>, RegionError> {
+        let entry = self.entries[index];
+
+        if !entry.is_present() {
+            return Ok(None);
+        }
+
+        let mut data = vec![0; entry.length as usize];
+        self.file
+            .seek(SeekFrom::Start(entry.sector as u64 * SECTOR_SIZE as u64))?;
+        self.file.read_exact(&mut data)?;
+
+        if checksum(&data) != entry.checksum {
+            return Err(RegionError::CorruptChunk(chunk_pos_in_region(index)));
+        }
+
+        let data = self
+            .codec
+            .decompress(&data)
+            .map_err(|_| RegionError::CorruptChunk(chunk_pos_in_region(index)))?;
+
+        postcard::from_bytes(&data)
+            .map(Some)
+            .map_err(|_| RegionError::CorruptChunk(chunk_pos_in_region(index)))
+    }
+
+    /// Writes the chunks to free sectors, and only then points the table at them. If the process
+    /// dies before the table is updated, the previous version of each chunk is still intact.
+    fn write_chunks(&mut self, chunks: Vec<(usize, Vec<u8>)>) -> io::Result<()> {
+        let mut entries = Vec::with_capacity(chunks.len());
+
+        // Stays set if any of the writes below fail, so that eviction knows to sync the file
+        self.dirty = true;
+
+        for (index, data) in chunks {
+            let sector = self.allocate(data.len().div_ceil(SECTOR_SIZE));
+
+            self.file
+                .seek(SeekFrom::Start(sector as u64 * SECTOR_SIZE as u64))?;
+            self.file.write_all(&data)?;
+
+            entries.push((
+                index,
+                ChunkEntry {
+                    sector,
+                    length: data.len() as u32,
+                    checksum: checksum(&data),
+                },
+            ));
+        }
+
+        self.file.sync_data()?;
+
+        for (index, entry) in entries {
+            self.write_entry(index, entry)?;
+
+            self.forget_chunk(index);
+            self.entries[index] = entry;
+        }
+
+        self.flush()
+    }
+
+    fn write_entry(&mut self, index: usize, entry: ChunkEntry) -> io::Result<()> {
+        self.file
+            .seek(SeekFrom::Start((SECTOR_SIZE + index * ENTRY_SIZE) as u64))?;
+        self.file.write_all(&entry.to_bytes())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.dirty {
+            self.file.sync_all()?;
+            self.dirty = false;
+        }
+
+        Ok(())
+    }
+
+    /// Copies a corrupt chunk next to the region file so that it can be inspected later, then
+    /// forgets it so it can be regenerated. The entry is cleared on disk before its sectors are
+    /// freed, so that it can't claim them back from the next chunk written there.
+    fn quarantine_chunk(&mut self, index: usize, path: &Path) -> io::Result<()> {
+        let entry = self.entries[index];
+
+        if entry.is_present() {
+            let mut data = vec![0; entry.length as usize];
+            self.file
+                .seek(SeekFrom::Start(entry.sector as u64 * SECTOR_SIZE as u64))?;
+            self.file.read_exact(&mut data)?;
+
+            let quarantine_path =
+                path.with_extension(format!("bin.chunk{index}.corrupt.{}", timestamp()));
+            write_atomic(&quarantine_path, &data)?;
+
+            self.write_entry(index, ChunkEntry::default())?;
+            self.file.sync_data()?;
+        }
+
+        self.forget_chunk(index);
+
+        Ok(())
+    }
+
+    /// Drops the chunk from the in-memory table and frees its sectors.
+    fn forget_chunk(&mut self, index: usize) {
+        let entry = std::mem::take(&mut self.entries[index]);
+
+        if entry.is_present() {
+            self.used_sectors[entry.sectors()].fill(false);
+        }
+    }
+
+    /// Finds the first run of free sectors large enough, or grows the file.
+    fn allocate(&mut self, count: usize) -> u32 {
+        let mut start = HEADER_SECTORS as usize;
+
+        while start < self.used_sectors.len() {
+            let end = (start + count).min(self.used_sectors.len());
+
+            match self.used_sectors[start..end].iter().rposition(|used| *used) {
+                Some(offset) => start += offset + 1,
+                None => break,
+            }
+        }
+
+        let end = start + count;
+
+        if end > self.used_sectors.len() {
+            self.used_sectors.resize(end, false);
+        }
+
+        self.used_sectors[start..end].fill(true);
+
+        start as u32
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct ChunkEntry {
+    sector: u32,
+    length: u32,
+    checksum: u64,
+}
+
+impl ChunkEntry {
+    fn from_bytes(bytes: &[u8]) -> Self {
+        Self {
+            sector: u32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+            length: u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+            checksum: u64::from_le_bytes(bytes[8..16].try_into().unwrap()),
+        }
+    }
+
+    fn to_bytes(self) -> [u8; ENTRY_SIZE] {
+        let mut bytes = [0; ENTRY_SIZE];
+        bytes[0..4].copy_from_slice(&self.sector.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.length.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.checksum.to_le_bytes());
+        bytes
+    }
+
+    fn is_present(&self) -> bool {
+        self.sector != 0
+    }
+
+    fn sectors(&self) -> std::ops::Range<usize> {
+        let start = self.sector as usize;
+        start..start + (self.length as usize).div_ceil(SECTOR_SIZE)
+    }
+}
+
+/// Builds a complete region file in memory, with the chunks laid out back to back.
+fn encode_region_file(codec: Codec, chunks: &[(usize, Vec<u8>)]) -> Vec<u8> {
+    let mut data = vec![0; HEADER_SECTORS as usize * SECTOR_SIZE];
+    data[0..4].copy_from_slice(&REGION_MAGIC);
+    data[4..8].copy_from_slice(&REGION_VERSION.to_le_bytes());
+    data[8] = codec.tag();
+
+    for (index, chunk) in chunks {
+        let entry = ChunkEntry {
+            sector: (data.len() / SECTOR_SIZE) as u32,
+            length: chunk.len() as u32,
+            checksum: checksum(chunk),
+        };
+
+        let offset = SECTOR_SIZE + index * ENTRY_SIZE;
+        data[offset..offset + ENTRY_SIZE].copy_from_slice(&entry.to_bytes());
+
+        data.extend_from_slice(chunk);
+        data.resize(data.len().next_multiple_of(SECTOR_SIZE), 0);
+    }
+
+    data
+}
+
+fn chunk_index(chunk_pos: IVec3) -> usize {
+    let local_pos = chunk_pos.rem_euclid(IVec3::splat(REGION_SIZE as i32));
+    local_pos.x as usize
+        + local_pos.y as usize * REGION_SIZE
+        + local_pos.z as usize * REGION_SIZE * REGION_SIZE
+}
+
+/// The position of a chunk relative to its region, used for error messages.
+fn chunk_pos_in_region(index: usize) -> IVec3 {
+    IVec3::new(
+        (index % REGION_SIZE) as i32,
+        (index / REGION_SIZE % REGION_SIZE) as i32,
+        (index / (REGION_SIZE * REGION_SIZE)) as i32,
+    )
+}
+
+fn migrate_region(
+    mut data: Vec<u8>,
+    version: u32,
+    codec: Codec,
+) -> Result<Option<Vec<u8>>, RegionError> {
+    for migration in &REGION_MIGRATIONS[version as usize..] {
+        let Some(migrated) = migration(&data, codec)? else {
+            return Ok(None);
+        };
+
+        data = migrated;
+    }
+
+    Ok(Some(data))
+}
+
+fn migrate_blob_to_sectors(data: &[u8], codec: Codec) -> Result<Option<Vec<u8>>, RegionError> {
+    let Some(legacy) = decode_legacy_region(data) else {
+        return Ok(None);
+    };
+
+    let mut chunks = Vec::new();
+
+    for (chunk_pos, chunk) in &legacy.chunks {
+        let chunk = postcard::to_allocvec(chunk)?;
+        chunks.push((chunk_index(*chunk_pos), codec.compress(&chunk)?));
+    }
+
+    Ok(Some(encode_region_file(codec, &chunks)))
+}
+
+/// Before sectors were introduced, each region was a single postcard blob. Newer blobs start
+/// with a header containing the length and a checksum of the payload.
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
-struct Region {
+struct LegacyRegion {
     chunks: IndexMap<IVec3, CompressedChunk>,
 }
 
+fn decode_legacy_region(data: &[u8]) -> Option<LegacyRegion> {
+    if !data.starts_with(&LEGACY_REGION_MAGIC) {
+        return postcard::from_bytes(data).ok();
+    }
+
+    if data.len() < LEGACY_REGION_HEADER_SIZE {
+        return None;
+    }
+
+    let length = u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize;
+    let expected_checksum = u64::from_le_bytes(data[8..16].try_into().unwrap());
+    let payload = &data[LEGACY_REGION_HEADER_SIZE..];
+
+    if payload.len() != length || checksum(payload) != expected_checksum {
+        return None;
+    }
+
+    postcard::from_bytes(payload).ok()
+}
+
+fn checksum(data: &[u8]) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+
+    for byte in data {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    hash
+}
+
+/// Writes to a temporary file next to the destination and renames it into place, so the
+/// destination is either the old or the new contents if the process dies mid-write.
+pub(crate) fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+
+    let mut file = File::create(&temp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&temp_path, path)
+}
+
+/// Moves a corrupt region file out of the way so that it can be inspected later, and the
+/// chunks inside of it can be regenerated.
+fn quarantine(path: &Path) -> io::Result<()> {
+    let quarantine_path = path.with_extension(format!("bin.corrupt.{}", timestamp()));
+
+    fs::rename(path, quarantine_path)
+}
+
+fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct CompressedChunk {
     palette: Vec<Block>,
@@ -139,31 +834,61 @@ impl CompressedChunk {
         }
     }
 
-    fn decompress(&self) -> ChunkData {
-        let mut data = Vec::new();
+    fn decompress(&self) -> Option<ChunkData> {
+        let mut data = Vec::with_capacity(CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE);
 
-        let mut i = 0;
+        for run in self.rle_data.chunks(2) {
+            let &[length, palette_index] = run else {
+                return None;
+            };
 
-        while i < self.rle_data.len() {
-            let palette_index = self.rle_data[i + 1];
-            let block = self.get_block(palette_index);
+            let block = self.get_block(palette_index)?;
 
-            for _ in 0..self.rle_data[i] {
-                data.push(block);
+            if data.len() + length as usize > data.capacity() {
+                return None;
             }
 
-            i += 2;
+            data.extend(std::iter::repeat_n(block, length as usize));
+        }
+
+        if data.len() != data.capacity() {
+            return None;
+        }
+
+        Some(Arc::new(ChunkDataInner::from_data(data)))
+    }
+
+    /// Maps every block in the palette, replacing blocks that map to `None` with air. Their
+    /// palette entries are kept so the indices of the other blocks don't change.
+    fn map_blocks(&mut self, map: impl Fn(Block) -> Option<Block>) {
+        let mut removed = vec![false; self.palette.len()];
+
+        for (block, removed) in self.palette.iter_mut().zip(&mut removed) {
+            match map(*block) {
+                Some(mapped) => *block = mapped,
+                None => *removed = true,
+            }
         }
 
-        Arc::new(ChunkDataInner::from_data(data))
+        for run in self.rle_data.chunks_mut(2) {
+            if let [_, palette_index] = run
+                && *palette_index != 0
+                && removed.get(*palette_index as usize - 1) == Some(&true)
+            {
+                *palette_index = 0;
+            }
+        }
     }
 
-    fn get_block(&self, palette_index: u16) -> Option<Block> {
+    fn get_block(&self, palette_index: u16) -> Option<Option<Block>> {
         if palette_index == 0 {
-            return None;
+            return Some(None);
         }
 
-        Some(self.palette[palette_index as usize - 1])
+        self.palette
+            .get(palette_index as usize - 1)
+            .copied()
+            .map(Some)
     }
 }
 
@@ -189,3 +914,260 @@ impl PaletteBuilder {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bevy::math::USizeVec3;
+
+    use crate::{BlockId, PackedData};
+
+    fn test_chunk(block_id: u16) -> ChunkData {
+        let mut data = ChunkDataInner::new();
+        data.set_block(
+            USizeVec3::new(1, 2, 3),
+            Some(Block::new(BlockId(block_id), PackedData::builder().build())),
+        );
+        Arc::new(data)
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("voxel-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn block_id_at(manager: &RegionManager, chunk_pos: IVec3) -> Option<BlockId> {
+        let chunk = manager.load_chunk(chunk_pos).unwrap()?;
+        chunk
+            .get_block(USizeVec3::new(1, 2, 3))
+            .map(|block| block.id)
+    }
+
+    #[test]
+    fn test_region_file() {
+        let dir = test_dir("region-file");
+        let manager = RegionManager::new(dir.clone()).unwrap();
+
+        let chunks = HashMap::from([
+            (IVec3::ZERO, test_chunk(1)),
+            (IVec3::new(-1, 3, 17), test_chunk(2)),
+        ]);
+        manager.save_chunks(&chunks).unwrap();
+
+        let path = manager.file_path(IVec3::ZERO);
+        let size = fs::metadata(&path).unwrap().len();
+
+        // Rewriting a chunk reuses the sectors freed by the previous version
+        manager
+            .save_chunks(&HashMap::from([(IVec3::ZERO, test_chunk(3))]))
+            .unwrap();
+        manager
+            .save_chunks(&HashMap::from([(IVec3::ZERO, test_chunk(4))]))
+            .unwrap();
+        assert!(fs::metadata(&path).unwrap().len() <= size * 2);
+
+        // A fresh manager reads the chunks back from disk
+        let manager = RegionManager::new(dir.clone()).unwrap();
+        assert_eq!(block_id_at(&manager, IVec3::ZERO), Some(BlockId(4)));
+        assert_eq!(
+            block_id_at(&manager, IVec3::new(-1, 3, 17)),
+            Some(BlockId(2))
+        );
+        assert!(manager.load_chunk(IVec3::ONE).unwrap().is_none());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_codecs() {
+        for &codec in Codec::ALL {
+            let dir = test_dir(&format!("codec-{codec}"));
+            let manager = RegionManager::with_codec(dir.clone(), codec).unwrap();
+
+            manager
+                .save_chunks(&HashMap::from([(IVec3::ZERO, test_chunk(1))]))
+                .unwrap();
+
+            // Regions keep their own codec, even when opened with a different default
+            let manager = RegionManager::with_codec(dir.clone(), Codec::None).unwrap();
+            assert_eq!(block_id_at(&manager, IVec3::ZERO), Some(BlockId(1)));
+
+            fs::remove_dir_all(dir).unwrap();
+        }
+
+        // The default is the same in every build, and codecs a build lacks are rejected up front
+        assert_eq!(Codec::default(), Codec::Lz4);
+        assert_eq!(
+            Codec::from_tag(Codec::Zstd.tag()).is_some(),
+            Codec::Zstd.is_supported()
+        );
+
+        if !Codec::Zstd.is_supported() {
+            let dir = test_dir("codec-unsupported");
+            assert!(matches!(
+                RegionManager::with_codec(dir.clone(), Codec::Zstd),
+                Err(RegionError::UnsupportedCodec(_))
+            ));
+            assert!(!dir.exists());
+        }
+    }
+
+    #[test]
+    fn test_evict_regions() {
+        let dir = test_dir("evict-regions");
+        let manager = RegionManager::new(dir.clone()).unwrap();
+
+        let chunks = (0..8)
+            .map(|x| (IVec3::new(x * REGION_SIZE as i32, 0, 0), test_chunk(1)))
+            .collect::<HashMap<_, _>>();
+        manager.save_chunks(&chunks).unwrap();
+        assert_eq!(manager.loaded_region_count(), 8);
+
+        // Regions near the center are kept until the limit is exceeded
+        manager.evict_regions(IVec3::ZERO, REGION_SIZE as i32, 8);
+        assert_eq!(manager.loaded_region_count(), 3);
+
+        // The region containing the center is always kept
+        manager.evict_regions(IVec3::ZERO, REGION_SIZE as i32, 0);
+        assert_eq!(manager.loaded_region_count(), 1);
+
+        // Evicted regions are reopened on demand
+        assert_eq!(
+            block_id_at(&manager, IVec3::new(7 * REGION_SIZE as i32, 0, 0)),
+            Some(BlockId(1))
+        );
+        assert_eq!(manager.loaded_region_count(), 2);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_region_file() {
+        let dir = test_dir("corrupt-region-file");
+        let manager = RegionManager::new(dir.clone()).unwrap();
+
+        manager
+            .save_chunks(&HashMap::from([(IVec3::ZERO, test_chunk(1))]))
+            .unwrap();
+
+        // Flipping a bit in the chunk data only quarantines that chunk
+        let path = manager.file_path(IVec3::ZERO);
+        let mut data = fs::read(&path).unwrap();
+        data[HEADER_SECTORS as usize * SECTOR_SIZE] ^= 1;
+        fs::write(&path, &data).unwrap();
+
+        let manager = RegionManager::new(dir.clone()).unwrap();
+        assert!(manager.load_chunk(IVec3::ZERO).unwrap().is_none());
+        assert!(manager.load_chunk(IVec3::ZERO).unwrap().is_none());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        // Its entry is cleared on disk too, so it can't take over sectors reused by other chunks
+        let entry_start = SECTOR_SIZE + chunk_index(IVec3::ZERO) * ENTRY_SIZE;
+        let header = fs::read(&path).unwrap();
+        assert_eq!(
+            header[entry_start..entry_start + ENTRY_SIZE],
+            [0; ENTRY_SIZE]
+        );
+
+        // Truncated headers quarantine the whole file
+        fs::write(&path, &data[..100]).unwrap();
+
+        let manager = RegionManager::new(dir.clone()).unwrap();
+        assert!(manager.load_chunk(IVec3::ZERO).unwrap().is_none());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 3);
+
+        // Entries overlapping others are ignored, and cleared on disk as well
+        manager
+            .save_chunks(&HashMap::from([
+                (IVec3::ZERO, test_chunk(1)),
+                (IVec3::X, test_chunk(2)),
+            ]))
+            .unwrap();
+
+        let first_start = SECTOR_SIZE + chunk_index(IVec3::ZERO) * ENTRY_SIZE;
+        let second_start = SECTOR_SIZE + chunk_index(IVec3::X) * ENTRY_SIZE;
+        let mut data = fs::read(&path).unwrap();
+        data.copy_within(first_start..first_start + ENTRY_SIZE, second_start);
+        fs::write(&path, &data).unwrap();
+
+        let manager = RegionManager::new(dir.clone()).unwrap();
+        assert_eq!(block_id_at(&manager, IVec3::ZERO), Some(BlockId(1)));
+        assert_eq!(block_id_at(&manager, IVec3::X), None);
+
+        let header = fs::read(&path).unwrap();
+        assert_eq!(
+            header[second_start..second_start + ENTRY_SIZE],
+            [0; ENTRY_SIZE]
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_legacy_region_file() {
+        let dir = test_dir("legacy-region-file");
+        let manager = RegionManager::new(dir.clone()).unwrap();
+
+        let mut legacy = LegacyRegion::default();
+        legacy
+            .chunks
+            .insert(IVec3::ONE, CompressedChunk::compress(&test_chunk(5)));
+        fs::write(
+            manager.file_path(IVec3::ZERO),
+            postcard::to_allocvec(&legacy).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(block_id_at(&manager, IVec3::ONE), Some(BlockId(5)));
+
+        // The original file is backed up before being migrated
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_newer_region_file() {
+        let dir = test_dir("newer-region-file");
+        let manager = RegionManager::new(dir.clone()).unwrap();
+
+        let mut data = encode_region_file(Codec::None, &[]);
+        data[4..8].copy_from_slice(&(REGION_VERSION + 1).to_le_bytes());
+        fs::write(manager.file_path(IVec3::ZERO), data).unwrap();
+
+        // Files from newer builds are left untouched rather than quarantined
+        assert!(matches!(
+            manager.load_chunk(IVec3::ZERO),
+            Err(RegionError::UnsupportedVersion(_))
+        ));
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_chunk_layout() {
+        // Changing the stored layout of chunks needs a new region version and a migration, not
+        // just an update to these bytes
+        let chunk = CompressedChunk::compress(&test_chunk(7));
+        assert_eq!(
+            postcard::to_allocvec(&chunk).unwrap(),
+            [1, 7, 0, 6, 193, 24, 0, 1, 1, 190, 231, 1, 0]
+        );
+    }
+
+    #[test]
+    fn test_corrupt_chunk() {
+        let mut chunk = CompressedChunk::compress(&test_chunk(1));
+
+        // Palette indices that are out of range are rejected
+        chunk.rle_data[1] = 100;
+        assert!(chunk.decompress().is_none());
+
+        // Runs that don't add up to a full chunk are rejected
+        chunk.rle_data = vec![10, 0];
+        assert!(chunk.decompress().is_none());
+    }
+}
@@ -1,22 +1,81 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::{Arc, OnceLock},
+};
 
-use crate::{Block, ChunkData, World};
+use crate::{Block, CHUNK_SIZE, ChunkData, ChunkLight, LOD_LEVELS, Light, LightChunks, World};
 
 use bevy::prelude::*;
 
+/// A chunk and its light downsampled to each LOD level, filled in by the first mesh task that
+/// needs them. Chunks replace theirs whenever they change, so that tasks still holding the old
+/// one can't fill it with outdated copies.
+#[derive(Debug, Default)]
+pub struct DownsampledChunk {
+    data: [OnceLock<ChunkData>; LOD_LEVELS],
+    light: [OnceLock<ChunkLight>; LOD_LEVELS],
+}
+
+impl DownsampledChunk {
+    pub fn clear(&mut self) {
+        for data in &mut self.data {
+            data.take();
+        }
+
+        for light in &mut self.light {
+            light.take();
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct RelevantChunks {
     chunks: HashMap<IVec3, ChunkData>,
+    lights: HashMap<IVec3, ChunkLight>,
+    downsampled: HashMap<IVec3, Arc<DownsampledChunk>>,
+    /// How many blocks each block stands for, when the chunks are downsampled.
+    scale: usize,
 }
 
 impl RelevantChunks {
+    pub fn new(chunks: HashMap<IVec3, ChunkData>) -> Self {
+        Self {
+            chunks,
+            lights: HashMap::new(),
+            downsampled: HashMap::new(),
+            scale: 1,
+        }
+    }
+
+    /// Collects a chunk and its neighbors. The blocks of neighbors at a different LOD level are
+    /// left out, so the faces along the seam are kept rather than hidden by blocks that are meshed
+    /// differently, but their light is still used to light those faces. Neighbors out of view are
+    /// open sky, and light the faces facing them fully.
     pub fn from_world(world: &World, center_pos: IVec3) -> Self {
         let mut chunks = HashMap::new();
+        let mut lights = HashMap::new();
+        let mut downsampled = HashMap::new();
+        let lod_level = world.lod_level(center_pos);
 
         for x in -1..=1 {
             for y in -1..=1 {
                 for z in -1..=1 {
                     let chunk_pos = center_pos + IVec3::new(x, y, z);
+
+                    if let Some(light) = world.get_chunk_light(chunk_pos) {
+                        lights.insert(chunk_pos, light);
+                    } else if world.is_open_sky(chunk_pos) {
+                        lights.insert(chunk_pos, ChunkLight::Uniform(Light::new(Light::MAX, 0)));
+                    }
+
+                    if let Some(chunk_downsampled) = world.get_downsampled(chunk_pos) {
+                        downsampled.insert(chunk_pos, chunk_downsampled);
+                    }
+
+                    if world.lod_level(chunk_pos) != lod_level {
+                        continue;
+                    }
+
                     if let Some(chunk) = world.get_chunk_data(chunk_pos) {
                         chunks.insert(chunk_pos, chunk);
                     }
@@ -24,13 +83,85 @@ impl RelevantChunks {
             }
         }
 
-        Self { chunks }
+        Self {
+            chunks,
+            lights,
+            downsampled,
+            scale: 1 << lod_level,
+        }
+    }
+
+    /// Downsamples the chunks to the scale of their LOD level. Afterwards, positions are in
+    /// downsampled blocks, with `CHUNK_SIZE / scale` blocks per chunk. Chunks that were already
+    /// downsampled for another mesh are reused.
+    pub fn downsampled(self) -> Self {
+        if self.scale == 1 {
+            return self;
+        }
+
+        let lod_index = self.scale.trailing_zeros() as usize - 1;
+
+        let chunks = self
+            .chunks
+            .iter()
+            .map(|(&chunk_pos, chunk)| {
+                let downsample = || Arc::new(chunk.downsample(self.scale));
+
+                let chunk = match self.downsampled.get(&chunk_pos) {
+                    Some(downsampled) => downsampled.data[lod_index].get_or_init(downsample),
+                    None => &downsample(),
+                };
+
+                (chunk_pos, chunk.clone())
+            })
+            .collect();
+
+        let lights = self
+            .lights
+            .iter()
+            .map(|(&chunk_pos, light)| {
+                let downsample = || light.downsample(self.scale);
+
+                let light = match self.downsampled.get(&chunk_pos) {
+                    Some(downsampled) => downsampled.light[lod_index].get_or_init(downsample),
+                    None => &downsample(),
+                };
+
+                (chunk_pos, light.clone())
+            })
+            .collect();
+
+        Self {
+            chunks,
+            lights,
+            downsampled: HashMap::new(),
+            scale: self.scale,
+        }
+    }
+
+    pub fn scale(&self) -> usize {
+        self.scale
     }
 
     pub fn get_block(&self, world_pos: IVec3) -> Option<Block> {
-        let chunk_pos = World::chunk_pos(world_pos);
-        let local_pos = World::local_pos(world_pos);
+        let chunk_size = IVec3::splat((CHUNK_SIZE / self.scale) as i32);
+        let chunk_pos = world_pos.div_euclid(chunk_size);
+        let local_pos = world_pos.rem_euclid(chunk_size).as_usizevec3();
         let chunk = self.chunks.get(&chunk_pos)?;
         chunk.get_block(local_pos)
     }
+
+    /// The light at a position, which is dark where there's no light to go by. Neighbors in view
+    /// only have no light while they're not lit yet, and lighting them later remeshes the chunks
+    /// whose borders they light.
+    pub fn get_light(&self, world_pos: IVec3) -> Light {
+        let chunk_size = IVec3::splat((CHUNK_SIZE / self.scale) as i32);
+        let chunk_pos = world_pos.div_euclid(chunk_size);
+        let local_pos = world_pos.rem_euclid(chunk_size).as_usizevec3();
+
+        match self.lights.get(&chunk_pos) {
+            Some(light) => light.get(local_pos),
+            None => Light::default(),
+        }
+    }
 }
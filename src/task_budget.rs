@@ -0,0 +1,126 @@
+use std::time::Duration;
+
+use bevy::tasks::available_parallelism;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TaskBudgetConfig {
+    /// How many tasks can be in flight for every available core.
+    pub tasks_per_core: f32,
+    pub min_tasks: usize,
+    pub max_tasks: usize,
+    /// Frames slower than this halve the budget, which then grows back by one task per frame.
+    pub frame_time_limit: Duration,
+}
+
+impl Default for TaskBudgetConfig {
+    fn default() -> Self {
+        Self {
+            tasks_per_core: 2.0,
+            min_tasks: 2,
+            max_tasks: 64,
+            frame_time_limit: Duration::from_secs_f32(1.0 / 30.0),
+        }
+    }
+}
+
+/// Limits how many background tasks of one kind can run at once, backing off when they start
+/// slowing down frames.
+#[derive(Debug, Clone)]
+pub struct TaskBudget {
+    config: TaskBudgetConfig,
+    cores: usize,
+    limit: usize,
+}
+
+impl TaskBudget {
+    pub fn new(config: TaskBudgetConfig) -> Self {
+        Self::with_cores(config, available_parallelism())
+    }
+
+    fn with_cores(config: TaskBudgetConfig, cores: usize) -> Self {
+        let mut budget = Self {
+            config,
+            cores,
+            limit: 0,
+        };
+        budget.limit = budget.max_limit();
+        budget
+    }
+
+    pub fn config(&self) -> TaskBudgetConfig {
+        self.config
+    }
+
+    pub fn set_config(&mut self, config: TaskBudgetConfig) {
+        self.config = config;
+        self.limit = self.limit.clamp(self.min_limit(), self.max_limit());
+    }
+
+    /// The number of tasks that can currently be in flight.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn has_room(&self, in_flight: usize) -> bool {
+        in_flight < self.limit
+    }
+
+    /// Adjusts the limit based on how long the last frame took.
+    pub fn update(&mut self, frame_time: Duration) {
+        self.limit = if frame_time > self.config.frame_time_limit {
+            self.limit / 2
+        } else {
+            self.limit + 1
+        }
+        .clamp(self.min_limit(), self.max_limit());
+    }
+
+    fn min_limit(&self) -> usize {
+        self.config.min_tasks.min(self.config.max_tasks)
+    }
+
+    fn max_limit(&self) -> usize {
+        ((self.cores as f32 * self.config.tasks_per_core).round() as usize)
+            .clamp(self.min_limit(), self.config.max_tasks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_task_budget() {
+        let config = TaskBudgetConfig::default();
+        let mut budget = TaskBudget::with_cores(config, 8);
+        assert_eq!(budget.limit(), 16);
+
+        // Slow frames halve the budget down to the minimum
+        let slow = config.frame_time_limit * 2;
+        budget.update(slow);
+        assert_eq!(budget.limit(), 8);
+
+        for _ in 0..10 {
+            budget.update(slow);
+        }
+
+        assert_eq!(budget.limit(), config.min_tasks);
+
+        // Fast frames grow it back one task at a time, up to what the cores allow
+        budget.update(Duration::ZERO);
+        assert_eq!(budget.limit(), config.min_tasks + 1);
+
+        for _ in 0..100 {
+            budget.update(Duration::ZERO);
+        }
+
+        assert_eq!(budget.limit(), 16);
+        assert!(!budget.has_room(16));
+
+        // Lots of cores are still capped
+        assert_eq!(
+            TaskBudget::with_cores(config, 128).limit(),
+            config.max_tasks
+        );
+    }
+}
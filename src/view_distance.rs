@@ -0,0 +1,171 @@
+use bevy::prelude::*;
+
+/// Which chunks around the player are loaded, measured in chunks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ViewDistance {
+    pub horizontal: i32,
+    pub vertical: i32,
+    pub shape: LoadShape,
+    /// The distances beyond which chunks are meshed at each LOD level, in increasing order.
+    /// Level `n` uses blocks `2^n` times as large.
+    pub lod_distances: [i32; LOD_LEVELS],
+}
+
+/// How many lower detail levels chunks can be meshed at, each halving the resolution.
+pub const LOD_LEVELS: usize = 3;
+
+/// How much the squared distance of a chunk directly behind the camera is scaled up.
+const BEHIND_PENALTY: f32 = 3.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadShape {
+    /// A circle of `horizontal` radius, extended `vertical` chunks up and down.
+    Cylinder,
+    /// An ellipsoid with `horizontal` and `vertical` radii, which skips the corners of the
+    /// cylinder that are furthest away.
+    Sphere,
+}
+
+impl Default for ViewDistance {
+    fn default() -> Self {
+        Self {
+            horizontal: 12,
+            vertical: 12,
+            shape: LoadShape::Cylinder,
+            lod_distances: [6, 8, 10],
+        }
+    }
+}
+
+impl ViewDistance {
+    /// Whether a chunk at `offset` from the center is within the view distance.
+    pub fn contains(&self, offset: IVec3) -> bool {
+        let horizontal = self.horizontal.max(0) as f32;
+        let vertical = self.vertical.max(0) as f32;
+        let horizontal_distance = offset.xz().as_vec2().length();
+        let vertical_distance = offset.y.abs() as f32;
+
+        match self.shape {
+            LoadShape::Cylinder => {
+                horizontal_distance <= horizontal && vertical_distance <= vertical
+            }
+            LoadShape::Sphere => {
+                let scaled = |distance: f32, radius: f32| {
+                    if radius == 0.0 {
+                        if distance == 0.0 { 0.0 } else { f32::INFINITY }
+                    } else {
+                        (distance / radius).powi(2)
+                    }
+                };
+
+                scaled(horizontal_distance, horizontal) + scaled(vertical_distance, vertical) <= 1.0
+            }
+        }
+    }
+
+    /// Every offset within the view distance, closest first, so loading can walk outwards from
+    /// the center without searching the whole bounding box.
+    pub fn offsets(&self) -> Vec<IVec3> {
+        let mut offsets = Vec::new();
+
+        for x in -self.horizontal..=self.horizontal {
+            for y in -self.vertical..=self.vertical {
+                for z in -self.horizontal..=self.horizontal {
+                    let offset = IVec3::new(x, y, z);
+
+                    if self.contains(offset) {
+                        offsets.push(offset);
+                    }
+                }
+            }
+        }
+
+        offsets.sort_by_key(|offset| Self::sort_key(*offset));
+        offsets
+    }
+
+    /// Orders chunks by horizontal distance from the center, then by vertical distance.
+    pub fn sort_key(offset: IVec3) -> (i32, i32) {
+        (offset.xz().length_squared(), offset.y.abs())
+    }
+
+    /// Like `sort_key`, but chunks away from `direction` count as further away, up to twice
+    /// their distance for chunks directly behind, so what's in view comes first.
+    pub fn sort_key_towards(offset: IVec3, direction: Vec3) -> (i32, i32) {
+        let (horizontal, vertical) = Self::sort_key(offset);
+        let alignment = offset.as_vec3().normalize_or_zero().dot(direction);
+        let scale = 1.0 + BEHIND_PENALTY * (1.0 - alignment) / 2.0;
+
+        ((horizontal as f32 * scale).round() as i32, vertical)
+    }
+
+    /// The LOD level to mesh a chunk at `offset` from the center at, where 0 is full detail.
+    pub fn lod_level(&self, offset: IVec3) -> usize {
+        let distance = offset.as_vec3().length();
+
+        self.lod_distances
+            .iter()
+            .take_while(|&&lod_distance| distance > lod_distance as f32)
+            .count()
+    }
+
+    /// The largest distance in any direction.
+    pub fn max_radius(&self) -> i32 {
+        self.horizontal.max(self.vertical)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_view_distance() {
+        let cylinder = ViewDistance {
+            horizontal: 8,
+            vertical: 2,
+            shape: LoadShape::Cylinder,
+            lod_distances: [4, 6, 7],
+        };
+
+        assert!(cylinder.contains(IVec3::new(8, 2, 0)));
+        assert!(!cylinder.contains(IVec3::new(0, 3, 0)));
+        assert!(!cylinder.contains(IVec3::new(6, 0, 6)));
+
+        let sphere = ViewDistance {
+            shape: LoadShape::Sphere,
+            ..cylinder
+        };
+
+        // The sphere drops the far corners of the cylinder
+        assert!(sphere.contains(IVec3::new(8, 0, 0)));
+        assert!(sphere.contains(IVec3::new(0, 2, 0)));
+        assert!(!sphere.contains(IVec3::new(8, 2, 0)));
+
+        // Offsets are sorted closest first and match the shape exactly
+        let offsets = sphere.offsets();
+        assert_eq!(offsets[0], IVec3::ZERO);
+        assert!(offsets.is_sorted_by_key(|offset| ViewDistance::sort_key(*offset)));
+        assert!(offsets.iter().all(|offset| sphere.contains(*offset)));
+        assert!(offsets.len() < cylinder.offsets().len());
+
+        // Chunks in view come before chunks at the same distance behind
+        let front = ViewDistance::sort_key_towards(IVec3::new(0, 0, -4), Vec3::NEG_Z);
+        let behind = ViewDistance::sort_key_towards(IVec3::new(0, 0, 4), Vec3::NEG_Z);
+        assert!(front < behind);
+        assert_eq!(front, ViewDistance::sort_key(IVec3::new(0, 0, -4)));
+        assert!(behind < ViewDistance::sort_key(IVec3::new(0, 0, -9)));
+
+        // Detail drops with distance
+        assert_eq!(cylinder.lod_level(IVec3::new(4, 0, 0)), 0);
+        assert_eq!(cylinder.lod_level(IVec3::new(3, 3, 0)), 1);
+        assert_eq!(cylinder.lod_level(IVec3::new(8, 0, 0)), 3);
+
+        // Every level is used within the default view distance
+        let default = ViewDistance::default();
+        assert_eq!(
+            default.lod_level(IVec3::new(default.horizontal, 0, 0)),
+            LOD_LEVELS
+        );
+    }
+}
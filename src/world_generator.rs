@@ -1,23 +1,43 @@
 use std::sync::Arc;
 
-use bevy::{
-    math::{DVec3, USizeVec3},
-    prelude::*,
-};
+use bevy::prelude::*;
 use noise::{NoiseFn, Perlin};
+use serde::{Deserialize, Serialize};
 
 use crate::{Block, CHUNK_SIZE, ChunkData, ChunkDataInner, PackedData, Registry};
 
+/// The seed every world used before it could be configured.
+pub const DEFAULT_SEED: u32 = 1337;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GeneratorSettings {
+    /// The average height of the terrain surface.
+    pub base_height: f64,
+    /// Multiplies the height of every noise layer.
+    pub height_scale: f64,
+    /// How many blocks of soil sit on top of the rock.
+    pub soil_depth: f64,
+}
+
+impl Default for GeneratorSettings {
+    fn default() -> Self {
+        Self {
+            base_height: 32.0,
+            height_scale: 1.0,
+            soil_depth: 3.0,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct WorldGenerator {
     perlin: Perlin,
+    settings: GeneratorSettings,
 }
 
 impl Default for WorldGenerator {
     fn default() -> Self {
-        Self {
-            perlin: Perlin::new(1337),
-        }
+        Self::with_settings(DEFAULT_SEED, GeneratorSettings::default())
     }
 }
 
@@ -26,6 +46,13 @@ impl WorldGenerator {
         Self::default()
     }
 
+    pub fn with_settings(seed: u32, settings: GeneratorSettings) -> Self {
+        Self {
+            perlin: Perlin::new(seed),
+            settings,
+        }
+    }
+
     pub fn generate_chunk(&self, chunk_pos: IVec3, registry: &Registry) -> ChunkData {
         let rock = registry.block_id("rock");
         let soil = registry.block_id("soil");
@@ -33,61 +60,79 @@ impl WorldGenerator {
         let loam = registry.material_id("loam");
         let lush_grass = registry.material_id("lush_grass");
 
-        let mut data = ChunkDataInner::new();
+        let chunk_origin = chunk_pos.as_dvec3() * CHUNK_SIZE as f64;
+
+        // The terrain is a heightmap, so the noise only needs sampling once per column
+        let mut heights = [[0.0; CHUNK_SIZE]; CHUNK_SIZE];
+
+        for (x, column) in heights.iter_mut().enumerate() {
+            for (z, height) in column.iter_mut().enumerate() {
+                *height = self.height(chunk_origin.x + x as f64, chunk_origin.z + z as f64);
+            }
+        }
+
+        let min_height = heights
+            .iter()
+            .flatten()
+            .copied()
+            .fold(f64::INFINITY, f64::min);
+        let max_height = heights
+            .iter()
+            .flatten()
+            .copied()
+            .fold(f64::NEG_INFINITY, f64::max);
+
+        let rock_block = Block::new(rock, PackedData::builder().with_material(shale).build());
+
+        // Chunks entirely above or below the surface don't need to look at every block
+        if chunk_origin.y >= max_height {
+            return Arc::new(ChunkDataInner::filled(None));
+        }
+
+        if chunk_origin.y + CHUNK_SIZE as f64 - 1.0 < min_height - self.settings.soil_depth {
+            return Arc::new(ChunkDataInner::filled(Some(rock_block)));
+        }
+
+        let mut data = vec![None; CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE];
 
         for x in 0..CHUNK_SIZE {
             for y in 0..CHUNK_SIZE {
                 for z in 0..CHUNK_SIZE {
-                    let local_pos = USizeVec3::new(x, y, z);
-
-                    let global_pos =
-                        DVec3::new(chunk_pos.x as f64, chunk_pos.y as f64, chunk_pos.z as f64)
-                            * CHUNK_SIZE as f64
-                            + DVec3::new(x as f64, y as f64, z as f64);
-
-                    let mountain_noise = self
-                        .perlin
-                        .get([global_pos.x / 400.0, global_pos.z / 400.0])
-                        * 40.0;
-
-                    let hill_noise = self
-                        .perlin
-                        .get([global_pos.x / 150.0, global_pos.z / 150.0])
-                        * 20.0;
-
-                    let detail_noise =
-                        self.perlin.get([global_pos.x / 50.0, global_pos.z / 50.0]) * 8.0;
-
-                    let fine_noise =
-                        self.perlin.get([global_pos.x / 15.0, global_pos.z / 15.0]) * 3.0;
-
-                    let height = 32.0 + mountain_noise + hill_noise + detail_noise + fine_noise;
-
-                    if global_pos.y < height - 3.0 {
-                        data.set_block(
-                            local_pos,
-                            Some(Block::new(
-                                rock,
-                                PackedData::builder().with_material(shale).build(),
-                            )),
-                        );
-                    } else if global_pos.y < height {
+                    let global_y = chunk_origin.y + y as f64;
+                    let height = heights[x][z];
+
+                    let block = if global_y < height - self.settings.soil_depth {
+                        Some(rock_block)
+                    } else if global_y < height {
                         let mut block_data = PackedData::builder().with_material(loam);
 
-                        if global_pos.y == height.ceil() - 1.0 {
+                        if global_y == height.ceil() - 1.0 {
                             block_data = block_data.with_bool(true).with_material(lush_grass);
                         } else {
                             block_data = block_data.with_bool(false);
                         }
 
-                        data.set_block(local_pos, Some(Block::new(soil, block_data.build())));
+                        Some(Block::new(soil, block_data.build()))
                     } else {
-                        data.set_block(local_pos, None);
-                    }
+                        None
+                    };
+
+                    data[x + y * CHUNK_SIZE + z * CHUNK_SIZE * CHUNK_SIZE] = block;
                 }
             }
         }
 
-        Arc::new(data)
+        Arc::new(ChunkDataInner::from_data(data))
+    }
+
+    /// The height of the terrain surface at a column of blocks.
+    fn height(&self, x: f64, z: f64) -> f64 {
+        let mountain_noise = self.perlin.get([x / 400.0, z / 400.0]) * 40.0;
+        let hill_noise = self.perlin.get([x / 150.0, z / 150.0]) * 20.0;
+        let detail_noise = self.perlin.get([x / 50.0, z / 50.0]) * 8.0;
+        let fine_noise = self.perlin.get([x / 15.0, z / 15.0]) * 3.0;
+
+        self.settings.base_height
+            + (mountain_noise + hill_noise + detail_noise + fine_noise) * self.settings.height_scale
     }
 }
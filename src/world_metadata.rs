@@ -0,0 +1,126 @@
+use std::{
+    fs, io,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{GeneratorSettings, RegionError, write_atomic};
+
+const WORLD_MAGIC: [u8; 4] = *b"VXWD";
+const WORLD_VERSION: u32 = 1;
+const METADATA_FILE_NAME: &str = "world.dat";
+
+/// Describes a saved world as a whole, stored next to its region files. Each region file has its
+/// own version, so this only needs to change when world-level data does.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorldMetadata {
+    pub seed: u32,
+    pub generator: GeneratorSettings,
+    /// Seconds since the Unix epoch.
+    pub created_at: u64,
+    /// The names of the blocks in the world's region files, indexed by their saved ids.
+    pub block_names: Vec<String>,
+    /// The names of the materials in the world's block data, indexed by their saved ids.
+    pub material_names: Vec<String>,
+}
+
+impl WorldMetadata {
+    pub fn new(seed: u32, generator: GeneratorSettings) -> Self {
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+
+        Self {
+            seed,
+            generator,
+            created_at,
+            block_names: Vec::new(),
+            material_names: Vec::new(),
+        }
+    }
+
+    /// Loads the metadata for the world in `dir`, or creates it if the world is new. Worlds saved
+    /// before metadata existed are given `default` too, which must match how they were generated.
+    pub fn load_or_create(dir: &Path, default: impl FnOnce() -> Self) -> Result<Self, RegionError> {
+        if let Some(metadata) = Self::load(dir)? {
+            return Ok(metadata);
+        }
+
+        let metadata = default();
+        metadata.save(dir)?;
+
+        Ok(metadata)
+    }
+
+    /// Whether `dir` contains a world, regardless of whether its metadata can be read.
+    pub fn exists(dir: &Path) -> bool {
+        dir.join(METADATA_FILE_NAME).is_file()
+    }
+
+    pub fn load(dir: &Path) -> Result<Option<Self>, RegionError> {
+        let data = match fs::read(dir.join(METADATA_FILE_NAME)) {
+            Ok(data) => data,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error.into()),
+        };
+
+        if data.len() < 8 || !data.starts_with(&WORLD_MAGIC) {
+            return Err(
+                io::Error::new(io::ErrorKind::InvalidData, "invalid world metadata").into(),
+            );
+        }
+
+        let version = u32::from_le_bytes(data[4..8].try_into().unwrap());
+
+        // Older versions will need to be upgraded here once the layout changes
+        match version {
+            WORLD_VERSION => Ok(Some(postcard::from_bytes(&data[8..])?)),
+            _ => Err(RegionError::UnsupportedVersion(version)),
+        }
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<(), RegionError> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&WORLD_MAGIC);
+        data.extend_from_slice(&WORLD_VERSION.to_le_bytes());
+        data.extend_from_slice(&postcard::to_allocvec(self)?);
+
+        write_atomic(&dir.join(METADATA_FILE_NAME), &data)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_world_metadata() {
+        let dir = std::env::temp_dir().join(format!("voxel-world-metadata-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let metadata = WorldMetadata::new(42, GeneratorSettings::default());
+        let loaded = WorldMetadata::load_or_create(&dir, || metadata.clone()).unwrap();
+        assert_eq!(loaded, metadata);
+
+        // Existing metadata takes priority over the default
+        let loaded = WorldMetadata::load_or_create(&dir, || unreachable!()).unwrap();
+        assert_eq!(loaded, metadata);
+
+        // Metadata from a newer build is rejected
+        let path = dir.join(METADATA_FILE_NAME);
+        let mut data = fs::read(&path).unwrap();
+        data[4..8].copy_from_slice(&(WORLD_VERSION + 1).to_le_bytes());
+        fs::write(&path, data).unwrap();
+        assert!(matches!(
+            WorldMetadata::load(&dir),
+            Err(RegionError::UnsupportedVersion(_))
+        ));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}